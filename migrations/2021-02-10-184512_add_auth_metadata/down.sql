@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE auths
+    DROP COLUMN id,
+    DROP COLUMN user_agent,
+    DROP COLUMN ip,
+    DROP COLUMN last_used;
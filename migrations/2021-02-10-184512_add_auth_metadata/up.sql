@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE auths
+    ADD COLUMN id character varying(24) NOT NULL UNIQUE DEFAULT substr(md5(random()::text), 1, 24),
+    ADD COLUMN user_agent character varying(255),
+    ADD COLUMN ip character varying(45),
+    ADD COLUMN last_used timestamp NOT NULL DEFAULT now();
+UPDATE auths SET last_used = created;
+ALTER TABLE auths
+    ALTER COLUMN id DROP DEFAULT,
+    ALTER COLUMN last_used DROP DEFAULT;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE used_refreshes
+(
+    refresh character varying(33) NOT NULL PRIMARY KEY,
+    family character varying(24) NOT NULL REFERENCES auths (id) ON DELETE CASCADE,
+    rotated timestamp NOT NULL
+)
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+-- Hashed tokens don't fit the old columns, so sessions are ended again.
+DELETE FROM auths;
+ALTER TABLE used_refreshes
+    ALTER COLUMN refresh TYPE character varying(33);
+ALTER TABLE auths
+    ALTER COLUMN token TYPE character varying(25),
+    ALTER COLUMN refresh TYPE character varying(33);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- Tokens are now stored as SHA-256 hex digests. Existing rows hold raw tokens
+-- that can never match a hashed lookup, so every session is ended here.
+-- used_refreshes rows are removed along with them through ON DELETE CASCADE.
+DELETE FROM auths;
+ALTER TABLE auths
+    ALTER COLUMN token TYPE character varying(64),
+    ALTER COLUMN refresh TYPE character varying(64);
+ALTER TABLE used_refreshes
+    ALTER COLUMN refresh TYPE character varying(64);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE password_resets
+(
+    token character varying(64) NOT NULL PRIMARY KEY,
+    owner character varying(23) NOT NULL REFERENCES users,
+    expiry timestamp NOT NULL,
+    created timestamp NOT NULL
+)
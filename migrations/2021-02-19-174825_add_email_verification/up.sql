@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Accounts created before verification existed are treated as verified.
+ALTER TABLE users
+    ADD COLUMN verified boolean NOT NULL DEFAULT true;
+ALTER TABLE users
+    ALTER COLUMN verified DROP DEFAULT;
+CREATE TABLE email_verifications
+(
+    token character varying(64) NOT NULL PRIMARY KEY,
+    owner character varying(23) NOT NULL REFERENCES users,
+    email character varying(191) NOT NULL,
+    expiry timestamp NOT NULL,
+    created timestamp NOT NULL
+)
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+ALTER TABLE users
+    ADD COLUMN totp_secret character varying(32),
+    ADD COLUMN totp_enabled boolean NOT NULL DEFAULT false,
+    ADD COLUMN totp_last_step bigint NOT NULL DEFAULT 0;
+CREATE TABLE recovery_codes
+(
+    owner character varying(23) NOT NULL REFERENCES users,
+    code character varying(64) NOT NULL,
+    PRIMARY KEY (owner, code)
+);
+CREATE TABLE login_challenges
+(
+    token character varying(64) NOT NULL PRIMARY KEY,
+    owner character varying(23) NOT NULL REFERENCES users,
+    expiry timestamp NOT NULL,
+    created timestamp NOT NULL
+)
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- Failed logins are tracked per account (by email) and per source IP.
+CREATE TABLE login_throttles
+(
+    scope character varying(8) NOT NULL,
+    key character varying(191) NOT NULL,
+    failures integer NOT NULL,
+    locked_until timestamp,
+    last_failure timestamp NOT NULL,
+    PRIMARY KEY (scope, key)
+)
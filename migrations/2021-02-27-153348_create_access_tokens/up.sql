@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE access_tokens
+(
+    id character varying(24) NOT NULL PRIMARY KEY,
+    owner character varying(23) NOT NULL REFERENCES users,
+    name character varying(64) NOT NULL,
+    token character varying(64) NOT NULL UNIQUE,
+    scopes text[] NOT NULL,
+    created timestamp NOT NULL,
+    last_used timestamp,
+    expiry timestamp
+)
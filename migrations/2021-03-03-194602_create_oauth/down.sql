@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM access_tokens WHERE client_id IS NOT NULL;
+ALTER TABLE access_tokens
+    DROP COLUMN client_id,
+    DROP COLUMN refresh;
+DROP TABLE oauth_codes;
+DROP TABLE oauth_clients;
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+CREATE TABLE oauth_clients
+(
+    id character varying(24) NOT NULL PRIMARY KEY,
+    owner character varying(23) NOT NULL REFERENCES users,
+    name character varying(64) NOT NULL,
+    secret character varying(64),
+    redirect_uris text[] NOT NULL,
+    created timestamp NOT NULL
+);
+CREATE TABLE oauth_codes
+(
+    code character varying(64) NOT NULL PRIMARY KEY,
+    client_id character varying(24) NOT NULL REFERENCES oauth_clients ON DELETE CASCADE,
+    owner character varying(23) NOT NULL REFERENCES users,
+    redirect_uri text NOT NULL,
+    scopes text[] NOT NULL,
+    code_challenge character varying(128) NOT NULL,
+    expiry timestamp NOT NULL
+);
+-- Tokens issued to OAuth clients live alongside personal access tokens.
+ALTER TABLE access_tokens
+    ADD COLUMN client_id character varying(24) REFERENCES oauth_clients ON DELETE CASCADE,
+    ADD COLUMN refresh character varying(64) UNIQUE;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE users
+    ADD COLUMN display_name character varying(50),
+    ADD COLUMN bio character varying(300),
+    ADD COLUMN location character varying(50),
+    ADD COLUMN website character varying(100),
+    ADD COLUMN nickname_changed timestamp
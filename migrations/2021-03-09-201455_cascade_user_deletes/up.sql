@@ -0,0 +1,29 @@
+-- Your SQL goes here
+ALTER TABLE auths
+    DROP CONSTRAINT auths_owner_fkey,
+    ADD CONSTRAINT auths_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE posts
+    DROP CONSTRAINT posts_owner_fkey,
+    ADD CONSTRAINT posts_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE password_resets
+    DROP CONSTRAINT password_resets_owner_fkey,
+    ADD CONSTRAINT password_resets_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE email_verifications
+    DROP CONSTRAINT email_verifications_owner_fkey,
+    ADD CONSTRAINT email_verifications_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE recovery_codes
+    DROP CONSTRAINT recovery_codes_owner_fkey,
+    ADD CONSTRAINT recovery_codes_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE login_challenges
+    DROP CONSTRAINT login_challenges_owner_fkey,
+    ADD CONSTRAINT login_challenges_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE access_tokens
+    DROP CONSTRAINT access_tokens_owner_fkey,
+    ADD CONSTRAINT access_tokens_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE oauth_clients
+    DROP CONSTRAINT oauth_clients_owner_fkey,
+    ADD CONSTRAINT oauth_clients_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE oauth_codes
+    DROP CONSTRAINT oauth_codes_owner_fkey,
+    ADD CONSTRAINT oauth_codes_owner_fkey FOREIGN KEY (owner) REFERENCES users ON DELETE CASCADE;
+ALTER TABLE users ADD COLUMN deletion_scheduled timestamp
\ No newline at end of file
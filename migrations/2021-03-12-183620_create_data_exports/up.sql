@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE data_exports
+(
+    id character varying(24) NOT NULL PRIMARY KEY,
+    owner character varying(23) NOT NULL REFERENCES users ON DELETE CASCADE,
+    status character varying(16) NOT NULL,
+    created timestamp NOT NULL,
+    completed timestamp,
+    expiry timestamp
+)
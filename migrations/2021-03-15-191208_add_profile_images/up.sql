@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE users
+    ADD COLUMN avatar character varying(64),
+    ADD COLUMN banner character varying(64)
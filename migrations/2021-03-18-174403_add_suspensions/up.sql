@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE users
+    ADD COLUMN status character varying(16) NOT NULL DEFAULT 'active'
+        CONSTRAINT users_status_check CHECK (status IN ('active', 'suspended')),
+    ADD COLUMN status_until timestamp
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+-- Bans and deactivations can't be kept, so those accounts are suspended until someone looks at them.
+UPDATE users SET status = 'suspended', status_until = NULL WHERE status IN ('banned', 'deactivated');
+ALTER TABLE users
+    DROP COLUMN status_reason,
+    DROP CONSTRAINT users_status_check,
+    ADD CONSTRAINT users_status_check CHECK (status IN ('active', 'suspended'));
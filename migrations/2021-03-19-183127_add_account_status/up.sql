@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE users
+    DROP CONSTRAINT users_status_check,
+    ADD CONSTRAINT users_status_check
+        CHECK (status IN ('active', 'suspended', 'banned', 'deactivated')),
+    ADD COLUMN status_reason character varying(500)
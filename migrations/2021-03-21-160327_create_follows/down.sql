@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE follow_counts;
+DROP TABLE follows;
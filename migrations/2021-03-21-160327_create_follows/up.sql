@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE follows
+(
+    follower character varying(23) NOT NULL REFERENCES users ON DELETE CASCADE,
+    followee character varying(23) NOT NULL REFERENCES users ON DELETE CASCADE,
+    created timestamp NOT NULL,
+    PRIMARY KEY (follower, followee)
+);
+CREATE INDEX follows_followee_idx ON follows (followee);
+-- Kept apart from users so saving a user never writes over the counts.
+CREATE TABLE follow_counts
+(
+    user_id character varying(23) PRIMARY KEY REFERENCES users ON DELETE CASCADE,
+    followers integer NOT NULL DEFAULT 0,
+    following integer NOT NULL DEFAULT 0
+)
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE follow_requests
+(
+    requester character varying(23) NOT NULL REFERENCES users ON DELETE CASCADE,
+    target character varying(23) NOT NULL REFERENCES users ON DELETE CASCADE,
+    created timestamp NOT NULL,
+    PRIMARY KEY (requester, target)
+);
+CREATE INDEX follow_requests_target_idx ON follow_requests (target);
+ALTER TABLE users ADD COLUMN protected boolean NOT NULL DEFAULT false
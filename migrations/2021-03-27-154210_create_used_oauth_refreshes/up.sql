@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE used_oauth_refreshes
+(
+    refresh character varying(64) NOT NULL PRIMARY KEY,
+    token_id character varying(24) NOT NULL REFERENCES access_tokens (id) ON DELETE CASCADE,
+    rotated timestamp NOT NULL
+)
@@ -0,0 +1,215 @@
+use crate::{
+    auth::{
+        permissions::{authorize, authorize_over, Permission, Role},
+        routes::require_session,
+        structure::{AccessToken, Auth},
+    },
+    error::StratError,
+    post::structure::Post,
+    user::{
+        routes::send_reset,
+        structure::{AccountStatus, AdminProfile, User},
+    },
+    util::{get_page, get_query, json_response, parse_body},
+};
+use hyper::{Body, Request, Response};
+use routerify::ext::RequestExt;
+
+// The longest a suspension can be given for, anything longer should be a ban.
+const MAX_SUSPENSION_DAYS: i64 = 3650;
+
+// Only lets staff through to the admin routes, each route checks its own permission too.
+// This goes after auth_middleware, since it needs the authenticated user.
+pub async fn admin_middleware(req: Request<Body>) -> Result<Request<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    authorize(&user, Permission::ViewUsers)?;
+    Ok(req)
+}
+
+// Searches users by nickname or email, 50 at a time.
+// Takes the query and page as query parameters ex: /admin/users?q=john&page=0
+pub async fn search_users(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let query = get_query(&req, "q").unwrap_or_default();
+    let users = User::search(&query, get_page(&req))?;
+    let mut results = Vec::new();
+    for user in users.iter() {
+        results.push(admin_view(user)?);
+    }
+    Ok(json_response(json!({"status": 200, "response": results})))
+}
+
+// Gets the account details of a user.
+pub async fn get_user_details(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let target = User::get_user(req.param("id").unwrap())?;
+    Ok(json_response(
+        json!({"status": 200, "response": admin_view(&target)?}),
+    ))
+}
+
+// Changes the role of a user.
+// Takes the role as the body ex: {"role": "moderator"}
+pub async fn change_role(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct RoleChange {
+        role: String,
+    }
+
+    let user = req.context::<User>().unwrap();
+    let mut target = User::get_user(req.param("id").unwrap())?;
+    let r: RoleChange = match parse_body::<RoleChange>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    let role = match Role::from_name(&r.role) {
+        Some(role) => role,
+        None => return Err(StratError::UnknownRole(r.role)),
+    };
+    authorize_over(&user, &target, Permission::ChangeRoles)?;
+    target.set_role(role);
+    target.save_user()?;
+    Ok(json_response(
+        json!({"status": 200, "response": admin_view(&target)?}),
+    ))
+}
+
+// Makes a user reset their password, their current one stops working
+// and every session is ended, then they're mailed a reset token.
+pub async fn force_password_reset(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let user = req.context::<User>().unwrap();
+    let mut target = User::get_user(req.param("id").unwrap())?;
+    authorize_over(&user, &target, Permission::ForcePasswordReset)?;
+    target.scramble_password();
+    target.save_user()?;
+    revoke_all(&target)?;
+    send_reset(&target)?;
+    Ok(json_response(
+        json!({"status": 200, "response": "Password reset forced, a reset token has been mailed to the user."}),
+    ))
+}
+
+// Ends every session and access token of a user.
+pub async fn revoke_user_sessions(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let user = req.context::<User>().unwrap();
+    let target = User::get_user(req.param("id").unwrap())?;
+    authorize_over(&user, &target, Permission::RevokeSessions)?;
+    let count = revoke_all(&target)?;
+    Ok(json_response(
+        json!({"status": 200, "response": "Successfully revoked all sessions!", "revoked": count}),
+    ))
+}
+
+// Suspends a user, ending every session they have.
+// Takes a reason and optionally how many days it lasts (up to 3650), leaving it out suspends them until unsuspended
+// ex: {"reason": "Spam", "days": 7}
+pub async fn suspend_user(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct Suspension {
+        reason: String,
+        days: Option<i64>,
+    }
+
+    let user = req.context::<User>().unwrap();
+    let mut target = User::get_user(req.param("id").unwrap())?;
+    let s: Suspension = match parse_body::<Suspension>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if s.reason.chars().count() > 500 {
+        return Err(StratError::OversizedField("reason".to_owned(), 500));
+    }
+    if let Some(days) = s.days {
+        if !(1..=MAX_SUSPENSION_DAYS).contains(&days) {
+            return Err(StratError::InvalidSuspension(MAX_SUSPENSION_DAYS));
+        }
+    }
+    authorize_over(&user, &target, Permission::SuspendUsers)?;
+    let until = s
+        .days
+        .map(|d| chrono::Utc::now().naive_utc() + chrono::Duration::days(d));
+    target.set_status(AccountStatus::Suspended(until), Some(s.reason));
+    target.save_user()?;
+    revoke_all(&target)?;
+    Ok(json_response(
+        json!({"status": 200, "response": admin_view(&target)?}),
+    ))
+}
+
+// Lifts a user's suspension.
+pub async fn unsuspend_user(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let user = req.context::<User>().unwrap();
+    let mut target = User::get_user(req.param("id").unwrap())?;
+    authorize_over(&user, &target, Permission::SuspendUsers)?;
+    // Bans and deactivations need more than a suspension to lift.
+    if let AccountStatus::Suspended(_) = target.get_status() {
+        target.set_status(AccountStatus::Active, None);
+        target.save_user()?;
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": admin_view(&target)?}),
+    ))
+}
+
+// Bans a user, ending every session they have.
+// Takes a reason as the body ex: {"reason": "Spam"}
+pub async fn ban_user(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    close_account(req, AccountStatus::Banned).await
+}
+
+// Deactivates a user, ending every session they have.
+// Takes a reason as the body ex: {"reason": "Requested by the owner"}
+pub async fn deactivate_user(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    close_account(req, AccountStatus::Deactivated).await
+}
+
+// Makes a banned, deactivated or suspended user active again.
+pub async fn reactivate_user(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let user = req.context::<User>().unwrap();
+    let mut target = User::get_user(req.param("id").unwrap())?;
+    authorize_over(&user, &target, Permission::BanUsers)?;
+    target.set_status(AccountStatus::Active, None);
+    target.save_user()?;
+    Ok(json_response(
+        json!({"status": 200, "response": admin_view(&target)?}),
+    ))
+}
+
+async fn close_account(
+    mut req: Request<Body>,
+    status: AccountStatus,
+) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct Closure {
+        reason: String,
+    }
+
+    let user = req.context::<User>().unwrap();
+    let mut target = User::get_user(req.param("id").unwrap())?;
+    let c: Closure = match parse_body::<Closure>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if c.reason.chars().count() > 500 {
+        return Err(StratError::OversizedField("reason".to_owned(), 500));
+    }
+    authorize_over(&user, &target, Permission::BanUsers)?;
+    target.set_status(status, Some(c.reason));
+    target.save_user()?;
+    revoke_all(&target)?;
+    Ok(json_response(
+        json!({"status": 200, "response": admin_view(&target)?}),
+    ))
+}
+
+fn admin_view(user: &User) -> Result<AdminProfile, StratError> {
+    let posts = Post::count_by_owner(user.get_id(), true)?;
+    let sessions = Auth::get_by_owner(user.get_id())?.len();
+    Ok(user.to_admin(posts, user.get_follow_counts()?, sessions))
+}
+
+// Ends every session and access token of a user, returning how many were removed.
+fn revoke_all(user: &User) -> Result<usize, StratError> {
+    let sessions = Auth::delete_all_for(user.get_id())?;
+    let tokens = AccessToken::delete_all_for(user.get_id())?;
+    Ok(sessions + tokens)
+}
@@ -0,0 +1,61 @@
+// Periodically deletes sessions that can no longer be used, so they don't pile up.
+// Accounts past their deletion grace period and expired data exports
+// are removed by the same sweep, which also fails exports whose build was lost.
+use super::structure::Auth;
+use crate::{
+    error::StratError,
+    user::structure::{DataExport, User},
+    util::config::CONFIG,
+};
+use std::time::{Duration, Instant};
+use tokio::{task, time};
+
+// Sweeps expired sessions forever, every SESSION_GC_INTERVAL_SECS.
+pub async fn collect_sessions() {
+    let mut interval = time::interval(Duration::from_secs(CONFIG.session_gc_interval));
+    loop {
+        interval.tick().await;
+        // Diesel blocks, so the sweep runs off the async workers.
+        match task::spawn_blocking(sweep).await {
+            Ok(Ok(swept)) => println!(
+                "Session GC removed {} expired sessions, {} used refreshes, {} deleted accounts and {} expired exports, and failed {} stale exports in {}ms",
+                swept.auths,
+                swept.refreshes,
+                swept.users,
+                swept.exports,
+                swept.stale_exports,
+                swept.took.as_millis()
+            ),
+            Ok(Err(e)) => eprintln!("Session GC failed: {}", e),
+            Err(e) => eprintln!("Session GC panicked: {}", e),
+        }
+    }
+}
+
+// How much a single sweep removed, and how long it took.
+struct Swept {
+    auths: usize,
+    refreshes: usize,
+    users: usize,
+    exports: usize,
+    stale_exports: usize,
+    took: Duration,
+}
+
+// Runs a single sweep.
+fn sweep() -> Result<Swept, StratError> {
+    let start = Instant::now();
+    let users = User::delete_due()?;
+    let auths = Auth::delete_expired()?;
+    let refreshes = Auth::delete_orphaned_refreshes()?;
+    let stale_exports = DataExport::fail_stale()?;
+    let exports = DataExport::delete_expired()?;
+    Ok(Swept {
+        auths,
+        refreshes,
+        users,
+        exports,
+        stale_exports,
+        took: start.elapsed(),
+    })
+}
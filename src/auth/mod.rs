@@ -1,2 +1,5 @@
+pub mod gc;
+pub mod permissions;
 pub mod routes;
 pub mod structure;
+pub mod totp;
@@ -0,0 +1,165 @@
+// Roles and the capabilities they grant, every permission check goes through here.
+use crate::{error::StratError, user::structure::User};
+
+// A user's role comes from users.rank, but only counts for accounts marked is_priv,
+// so staff can be demoted by clearing one flag without losing their rank.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn of(user: &User) -> Self {
+        if !user.is_priv() {
+            return Role::User;
+        }
+        match user.get_rank() {
+            r if r >= Role::Admin.rank() => Role::Admin,
+            r if r >= Role::Moderator.rank() => Role::Moderator,
+            _ => Role::User,
+        }
+    }
+
+    // The rank stored for this role.
+    pub fn rank(self) -> i32 {
+        match self {
+            Role::User => 0,
+            Role::Moderator => 1,
+            Role::Admin => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "user" => Some(Role::User),
+            "moderator" => Some(Role::Moderator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+// Something only staff can do.
+#[derive(Clone, Copy, Debug)]
+pub enum Permission {
+    // Edit posts made by other users.
+    EditAnyPost,
+    // Delete posts made by other users.
+    DeleteAnyPost,
+    // Delete OAuth clients registered by other users.
+    DeleteAnyClient,
+    // Search users and view their account details.
+    ViewUsers,
+    // Suspend and unsuspend users.
+    SuspendUsers,
+    // Ban, deactivate and reactivate users.
+    BanUsers,
+    // End every session of other users.
+    RevokeSessions,
+    // Make other users reset their password.
+    ForcePasswordReset,
+    // Change the role of other users.
+    ChangeRoles,
+}
+
+impl Permission {
+    pub fn name(self) -> &'static str {
+        match self {
+            Permission::EditAnyPost => "edit_any_post",
+            Permission::DeleteAnyPost => "delete_any_post",
+            Permission::DeleteAnyClient => "delete_any_client",
+            Permission::ViewUsers => "view_users",
+            Permission::SuspendUsers => "suspend_users",
+            Permission::BanUsers => "ban_users",
+            Permission::RevokeSessions => "revoke_sessions",
+            Permission::ForcePasswordReset => "force_password_reset",
+            Permission::ChangeRoles => "change_roles",
+        }
+    }
+
+    // The lowest role that has this permission, every role above it has it too.
+    fn min_role(self) -> Role {
+        match self {
+            Permission::EditAnyPost => Role::Admin,
+            Permission::DeleteAnyPost => Role::Moderator,
+            Permission::DeleteAnyClient => Role::Admin,
+            Permission::ViewUsers => Role::Moderator,
+            Permission::SuspendUsers => Role::Moderator,
+            Permission::BanUsers => Role::Admin,
+            Permission::RevokeSessions => Role::Admin,
+            Permission::ForcePasswordReset => Role::Admin,
+            Permission::ChangeRoles => Role::Admin,
+        }
+    }
+}
+
+// Checks the user has a permission.
+pub fn authorize(user: &User, permission: Permission) -> Result<(), StratError> {
+    if Role::of(user) >= permission.min_role() {
+        Ok(())
+    } else {
+        Err(StratError::MissingPermission(permission.name().to_owned()))
+    }
+}
+
+// Checks the user either owns something, or has the permission to act on it anyway.
+// Staff acting on something they don't own is logged.
+pub fn authorize_owned(user: &User, owner: &str, permission: Permission) -> Result<(), StratError> {
+    if user.get_id() == owner {
+        return Ok(());
+    }
+    match authorize(user, permission) {
+        Ok(_) => {
+            println!(
+                "{} {} used {} on something owned by {}",
+                Role::of(user).name(),
+                user.get_id(),
+                permission.name(),
+                owner
+            );
+            Ok(())
+        }
+        Err(_e) => Err(StratError::NoPermission),
+    }
+}
+
+// Checks the user owns something that nobody else can act on, not even staff.
+// Otherwise the given error is returned, which should say it's missing so IDs can't be probed.
+pub fn authorize_self(user: &User, owner: &str, missing: StratError) -> Result<(), StratError> {
+    if user.get_id() == owner {
+        Ok(())
+    } else {
+        Err(missing)
+    }
+}
+
+// Checks the user has a permission and outranks the user it's being used on,
+// so staff can't act on each other unless they're above them.
+pub fn authorize_over(
+    user: &User,
+    target: &User,
+    permission: Permission,
+) -> Result<(), StratError> {
+    authorize(user, permission)?;
+    if Role::of(target) >= Role::of(user) {
+        return Err(StratError::TargetOutranks);
+    }
+    println!(
+        "{} {} used {} on {}",
+        Role::of(user).name(),
+        user.get_id(),
+        permission.name(),
+        target.get_id()
+    );
+    Ok(())
+}
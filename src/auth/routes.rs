@@ -1,12 +1,25 @@
-use super::structure::{Auth, AuthRefresh, AuthToken};
+use super::{
+    permissions::authorize_self,
+    structure::{
+        AccessToken, Auth, AuthRefresh, AuthSecrets, AuthSession, AuthToken, LoginChallenge,
+        LoginThrottle, RecoveryCode, Scopes, ACCESS_TOKEN_PREFIX,
+    },
+    totp,
+};
 use crate::{
     error::StratError,
     user::structure::{User, UserLoginable},
-    util::{json_response, parse_body, parse_cookies},
+    util::{
+        constant_eq, cookie_header, gen_random, json_response, parse_bearer, parse_body,
+        parse_cookies,
+    },
 };
-use hyper::{header::HeaderValue, Body, Request, Response};
+use hyper::{header::USER_AGENT, Body, Method, Request, Response};
 use routerify::ext::RequestExt;
 
+// The longest a personal access token can be made to last, leaving it out means it never expires.
+const MAX_ACCESS_TOKEN_DAYS: i64 = 3650;
+
 // Authenticates an account and returns the refresh and token
 // Takes a UserLoginable struct as the request body ex: {"email": "johndoe@example.com", "password": "password"}
 pub async fn login(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
@@ -14,64 +27,264 @@ pub async fn login(mut req: Request<Body>) -> Result<Response<Body>, StratError>
         Ok(val) => val,
         Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
     };
+    let ip = req.remote_addr().ip().to_string();
+    if let Some(e) = LoginThrottle::check(&ip, &u.email) {
+        return Err(e);
+    }
     let user = match User::get_by_login(&u.email, &u.password) {
+        Ok(u) => u,
+        Err(StratError::BadLogin) => {
+            if let Some(e) = LoginThrottle::record_failure(&ip, &u.email) {
+                return Err(e);
+            }
+            return Err(StratError::BadLogin);
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some(e) = LoginThrottle::clear(&u.email) {
+        return Err(e);
+    }
+    // Accounts with two-factor authentication get a challenge instead of a session.
+    if user.is_totp_enabled() {
+        return match LoginChallenge::create(user.get_id().to_owned()) {
+            Ok(challenge) => Ok(json_response(
+                json!({"status": 200, "response": "Two-factor authentication required", "challenge": challenge}),
+            )),
+            Err(e) => Err(e),
+        };
+    }
+    start_session(&req, &user)
+}
+
+// Completes a login for an account with two-factor authentication.
+// Takes the challenge from login and a code from the authenticator (or a recovery code)
+// ex: {"challenge": "ABCDEFGHIJ...", "code": "123456"}
+pub async fn login_totp(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct TotpLogin {
+        challenge: String,
+        code: String,
+    }
+
+    let t: TotpLogin = match parse_body::<TotpLogin>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    let owner = match LoginChallenge::consume(&t.challenge) {
+        Ok(o) => o,
+        Err(e) => return Err(e),
+    };
+    let mut user = match User::get_user(&owner) {
         Ok(u) => u,
         Err(e) => return Err(e),
     };
-    let mut auth = Auth::new(user.get_id().to_owned());
+    if !check_second_factor(&mut user, &t.code)? {
+        return Err(StratError::BadTotp);
+    }
+    start_session(&req, &user)
+}
+
+// Starts enrolling two-factor authentication, returning the otpauth URI to scan.
+pub async fn enroll_totp(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let mut user = req.context::<User>().unwrap();
+    if user.is_totp_enabled() {
+        return Err(StratError::TotpEnabled);
+    }
+    let secret = user.begin_totp();
+    user.save_user()?;
+    Ok(json_response(json!({
+        "status": 200,
+        "response": "Confirm a code from your authenticator to enable two-factor authentication.",
+        "secret": secret,
+        "uri": totp::provisioning_uri(&secret, user.get_email())
+    })))
+}
+
+// Enables two-factor authentication once a code from the enrolled secret is confirmed.
+// Returns recovery codes, which are only ever shown here.
+// Takes the code as the body ex: {"code": "123456"}
+pub async fn confirm_totp(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct TotpConfirm {
+        code: String,
+    }
+
+    require_session(&req)?;
+    let mut user = req.context::<User>().unwrap();
+    let t: TotpConfirm = match parse_body::<TotpConfirm>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if user.is_totp_enabled() {
+        return Err(StratError::TotpEnabled);
+    }
+    if !user.verify_totp(&t.code) {
+        return Err(StratError::BadTotp);
+    }
+    user.enable_totp();
+    user.save_user()?;
+    let codes = totp::generate_recovery_codes();
+    if let Some(e) = RecoveryCode::replace_for(user.get_id(), &codes) {
+        return Err(e);
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": "Two-factor authentication enabled!", "recovery_codes": codes}),
+    ))
+}
+
+// Disables two-factor authentication, requiring the password and a current code.
+// Takes the password and code as the body ex: {"password": "password", "code": "123456"}
+pub async fn disable_totp(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct TotpDisable {
+        password: String,
+        code: String,
+    }
+
+    require_session(&req)?;
+    let mut user = req.context::<User>().unwrap();
+    let t: TotpDisable = match parse_body::<TotpDisable>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if !user.is_totp_enabled() {
+        return Err(StratError::TotpDisabled);
+    }
+    if !user.check_password(&t.password) {
+        return Err(StratError::BadLogin);
+    }
+    if !check_second_factor(&mut user, &t.code)? {
+        return Err(StratError::BadTotp);
+    }
+    user.disable_totp();
+    user.save_user()?;
+    if let Some(e) = RecoveryCode::delete_for(user.get_id()) {
+        return Err(e);
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": "Two-factor authentication disabled!"}),
+    ))
+}
+
+// Checks a code from the user's authenticator, falling back to their recovery codes.
+// A valid authenticator code is saved as used so it can't be replayed.
+fn check_second_factor(user: &mut User, code: &str) -> Result<bool, StratError> {
+    if user.verify_totp(code) {
+        user.save_user()?;
+        return Ok(true);
+    }
+    RecoveryCode::consume(user.get_id(), code)
+}
+
+// Creates an Auth for a user and sets its cookies on the response.
+fn start_session(req: &Request<Body>, user: &User) -> Result<Response<Body>, StratError> {
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .map(|ua| ua.to_owned());
+    let (mut auth, secrets) = Auth::new(
+        user.get_id().to_owned(),
+        user_agent,
+        Some(req.remote_addr().ip().to_string()),
+    );
+    // Logging in is how a scheduled account deletion is cancelled.
+    let mut owner = user.clone();
+    let cancelled = owner.cancel_deletion();
+    if cancelled {
+        owner.save_user()?;
+    }
     match auth.save_auth() {
         None => {
-            let mut response =
-                json_response(json!({"status": 200, "response": "Authorization created"}));
-            // Append Refresh Token as a cookie, limiting it to "/auth/refresh"
-            response.headers_mut().append(
-                "Set-Cookie",
-                HeaderValue::from_str(&format!(
-                    "X-AUTH-REFRESH={};Path=/auth/refresh;HttpOnly",
-                    auth.get_refresh()
-                ))
-                .unwrap(),
-            );
-            // Append the Auth Token as a cookie
-            response.headers_mut().append(
-                "Set-Cookie",
-                HeaderValue::from_str(&format!(
-                    "X-AUTH-TOKEN={};Path=/;HttpOnly",
-                    auth.get_token()
-                ))
-                .unwrap(),
+            let mut response = json_response(
+                json!({"status": 200, "response": "Authorization created", "deletion_cancelled": cancelled}),
             );
-            return Ok(response);
+            set_auth_cookies(&mut response, &auth, &secrets);
+            set_csrf_cookie(&mut response, &auth);
+            Ok(response)
         }
         Some(e) => Err(e),
     }
 }
 
 // Refreshes an Auth and returns the token
-// Takes a AuthRefresh struct as the request body ex: {"refresh": "ABCDEFGHIJKLMNOPQRSTUVWXY"}
-pub async fn refresh(req: Request<Body>) -> Result<Response<Body>, StratError> {
+// The refresh token is rotated, so both cookies are replaced.
+// Clients without cookies can send the refresh in an "X-AUTH-REFRESH" header,
+// or as the request body ex: {"refresh": "ABCDEFGHIJKLMNOPQRSTUVWXYZABCDEFG"}
+pub async fn refresh(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct RefreshBody {
+        refresh: String,
+    }
+
     let cookies = parse_cookies(req.headers());
+    let from_cookie = cookies.get("X-AUTH-REFRESH").is_some();
     let refresh = if let Some(token) = cookies.get("X-AUTH-REFRESH") {
         AuthRefresh::new(token.value().to_owned())
+    } else if let Some(token) = req
+        .headers()
+        .get("X-AUTH-REFRESH")
+        .and_then(|t| t.to_str().ok())
+    {
+        AuthRefresh::new(token.to_owned())
     } else {
-        return Err(StratError::InvalidRefresh);
-    };
-    let auth = match refresh.is_valid() {
-        true => refresh.to_auth().unwrap(),
-        false => return Err(StratError::UnknownRefresh),
+        match parse_body::<RefreshBody>(&mut req).await {
+            Ok(body) => AuthRefresh::new(body.refresh),
+            Err(_e) => return Err(StratError::InvalidRefresh),
+        }
     };
-    match auth.refresh() {
-        Ok(_s) => Ok(json_response(
-            json!({"status": 200, "response": "Authorization updated!", "token": auth.get_token()}),
-        )),
+    match refresh.rotate() {
+        Ok((auth, secrets)) if from_cookie => {
+            let mut response = json_response(
+                json!({"status": 200, "response": "Authorization updated!", "token": secrets.get_token()}),
+            );
+            set_auth_cookies(&mut response, &auth, &secrets);
+            Ok(response)
+        }
+        // The refresh was rotated, so clients that don't use cookies need the new one.
+        Ok((_auth, secrets)) => Ok(json_response(json!({
+            "status": 200,
+            "response": "Authorization updated!",
+            "token": secrets.get_token(),
+            "refresh": secrets.get_refresh()
+        }))),
         Err(e) => Err(e),
     }
 }
 
 //Authenticates am account
+// The token is taken from an "Authorization: Bearer" header, or the "X-AUTH-TOKEN" cookie.
 pub async fn auth_middleware(req: Request<Body>) -> Result<Request<Body>, StratError> {
+    let (user, auth, scopes) = authenticate(&req)?;
+    req.set_context(user);
+    // Access tokens don't have an Auth, which is how require_session tells them apart.
+    if let Some(auth) = auth {
+        req.set_context(auth);
+    }
+    req.set_context(scopes);
+    Ok(req)
+}
+
+// Gets the user a request is authenticated as, for routes that work without logging in too.
+// Access tokens need the posts:read scope to be counted.
+pub fn get_viewer(req: &Request<Body>) -> Option<User> {
+    match authenticate(req) {
+        Ok((user, _, scopes)) if scopes.allows("posts:read") => Some(user),
+        _ => None,
+    }
+}
+
+// Works out who a request is authenticated as, with their session if it isn't an access token.
+fn authenticate(req: &Request<Body>) -> Result<(User, Option<Auth>, Scopes), StratError> {
     let cookies = parse_cookies(req.headers());
-    let token = if let Some(token) = cookies.get("X-AUTH-TOKEN") {
+    let token = if let Some(token) = parse_bearer(req.headers()) {
+        // Access tokens are only ever sent as Bearer tokens.
+        if token.starts_with(ACCESS_TOKEN_PREFIX) {
+            return authenticate_access_token(&token);
+        }
+        AuthToken::new(token)
+    } else if let Some(token) = cookies.get("X-AUTH-TOKEN") {
         AuthToken::new(token.value().to_owned())
     } else {
         return Err(StratError::InvalidToken);
@@ -79,10 +292,253 @@ pub async fn auth_middleware(req: Request<Body>) -> Result<Request<Body>, StratE
     if !token.is_valid() {
         return Err(StratError::InvalidToken);
     }
-    let user = match User::get_user(token.to_auth().unwrap().get_owner()) {
-        Ok(u) => u,
+    let mut auth = token.to_auth().unwrap();
+    if let Some(e) = auth.touch() {
+        return Err(e);
+    }
+    let user = User::get_user(auth.get_owner())?;
+    user.check_active()?;
+    Ok((user, Some(auth), Scopes::All))
+}
+
+// Authenticates an account using a personal access token, limiting it to the token's scopes.
+fn authenticate_access_token(token: &str) -> Result<(User, Option<Auth>, Scopes), StratError> {
+    let mut access = match AccessToken::get_by_token(token) {
+        Ok(a) => a,
+        Err(_e) => return Err(StratError::InvalidToken),
+    };
+    if let Some(e) = access.touch() {
+        return Err(e);
+    }
+    let user = User::get_user(access.get_owner())?;
+    user.check_active()?;
+    Ok((user, None, access.to_scopes()))
+}
+
+// Gets the Auth of the request.
+// Account management needs a real session, so this fails for access tokens.
+pub fn require_session(req: &Request<Body>) -> Result<Auth, StratError> {
+    match req.context::<Auth>() {
+        Some(a) => Ok(a),
+        None => Err(StratError::SessionRequired),
+    }
+}
+
+// Creates a personal access token, the raw token is only ever returned here.
+// Takes a name, scopes and optionally how many days it lasts (up to 3650)
+// ex: {"name": "My Bot", "scopes": ["posts:read", "posts:write"], "expires_in_days": 90}
+pub async fn create_access_token(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct AccessTokenCreate {
+        name: String,
+        scopes: Vec<String>,
+        expires_in_days: Option<i64>,
+    }
+
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    let a: AccessTokenCreate = match parse_body::<AccessTokenCreate>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if a.name.is_empty() || a.name.chars().count() > 64 {
+        return Err(StratError::OversizedField("name".to_owned(), 64));
+    }
+    if let Some(days) = a.expires_in_days {
+        if !(1..=MAX_ACCESS_TOKEN_DAYS).contains(&days) {
+            return Err(StratError::InvalidTokenLifetime(MAX_ACCESS_TOKEN_DAYS));
+        }
+    }
+    let expiry = a
+        .expires_in_days
+        .map(|days| chrono::Local::now().naive_local() + chrono::Duration::days(days));
+    let (access, token) = AccessToken::new(user.get_id().to_owned(), a.name, a.scopes, expiry)?;
+    if let Some(e) = access.save_token() {
+        return Err(e);
+    }
+    Ok(json_response(json!({
+        "status": 200,
+        "response": "Access token created! It won't be shown again, so store it somewhere safe.",
+        "id": access.get_id(),
+        "token": token
+    })))
+}
+
+// Lists the personal access tokens of the authenticated user, without the tokens themselves.
+pub async fn list_access_tokens(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    match AccessToken::get_by_owner(user.get_id()) {
+        Ok(tokens) => Ok(json_response(json!({"status": 200, "response": tokens}))),
+        Err(e) => Err(e),
+    }
+}
+
+// Deletes one of the authenticated user's personal access tokens using its ID.
+pub async fn delete_access_token(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    let access = match AccessToken::get_by_id(req.param("id").unwrap()) {
+        Ok(a) => a,
         Err(e) => return Err(e),
     };
-    req.set_context(user);
+    authorize_self(&user, access.get_owner(), StratError::UnknownAccessToken)?;
+    match access.delete_token() {
+        None => Ok(json_response(
+            json!({"status": 200, "response": "Access token deleted!"}),
+        )),
+        Some(e) => Err(e),
+    }
+}
+
+// Rejects state-changing requests authenticated by cookie unless the
+// "X-CSRF-TOKEN" header matches the "X-CSRF-TOKEN" cookie issued at login.
+// Requests using a Bearer token can't be forged by a browser, so they're let through.
+pub async fn csrf_middleware(req: Request<Body>) -> Result<Request<Body>, StratError> {
+    match *req.method() {
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE => {}
+        _ => return Ok(req),
+    }
+    if parse_bearer(req.headers()).is_some() {
+        return Ok(req);
+    }
+    let cookies = parse_cookies(req.headers());
+    let cookie = match cookies.get("X-CSRF-TOKEN") {
+        Some(c) => c.value().to_owned(),
+        None => return Err(StratError::CsrfFailed),
+    };
+    let header = match req
+        .headers()
+        .get("X-CSRF-TOKEN")
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(h) => h,
+        None => return Err(StratError::CsrfFailed),
+    };
+    if cookie.is_empty() || !constant_eq(&cookie, header) {
+        return Err(StratError::CsrfFailed);
+    }
     Ok(req)
 }
+
+// Ends the current session, deleting its Auth and clearing the auth cookies.
+pub async fn logout(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let auth = require_session(&req)?;
+    if let Some(e) = auth.delete_auth() {
+        return Err(e);
+    }
+    let mut response =
+        json_response(json!({"status": 200, "response": "Successfully logged out!"}));
+    clear_auth_cookies(&mut response);
+    Ok(response)
+}
+
+// Ends every session belonging to the authenticated user, including the current one.
+pub async fn logout_all(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    let count = match Auth::delete_all_for(user.get_id()) {
+        Ok(n) => n,
+        Err(e) => return Err(e),
+    };
+    let mut response = json_response(
+        json!({"status": 200, "response": "Successfully logged out of all sessions!", "revoked": count}),
+    );
+    clear_auth_cookies(&mut response);
+    Ok(response)
+}
+
+// Lists every active session of the authenticated user, without exposing their tokens.
+pub async fn list_sessions(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let user = req.context::<User>().unwrap();
+    let current = require_session(&req)?;
+    let sessions: Vec<AuthSession> = match Auth::get_by_owner(user.get_id()) {
+        Ok(auths) => auths
+            .iter()
+            .map(|a| a.to_session(current.get_id()))
+            .collect(),
+        Err(e) => return Err(e),
+    };
+    Ok(json_response(json!({"status": 200, "response": sessions})))
+}
+
+// Revokes a single session of the authenticated user using its ID.
+pub async fn revoke_session(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let user = req.context::<User>().unwrap();
+    let current = require_session(&req)?;
+    let id = req.param("id").unwrap();
+    let auth = match Auth::get_by_id(id) {
+        Ok(a) => a,
+        Err(e) => return Err(e),
+    };
+    authorize_self(&user, auth.get_owner(), StratError::UnknownSession)?;
+    let is_current = auth.get_id() == current.get_id();
+    if let Some(e) = auth.delete_auth() {
+        return Err(e);
+    }
+    let mut response = json_response(json!({"status": 200, "response": "Session revoked!"}));
+    if is_current {
+        clear_auth_cookies(&mut response);
+    }
+    Ok(response)
+}
+
+// Appends the raw refresh and token of an Auth as cookies.
+// Both cookies expire along with what they hold.
+fn set_auth_cookies(response: &mut Response<Body>, auth: &Auth, secrets: &AuthSecrets) {
+    let now = chrono::Local::now().naive_local();
+    // Append Refresh Token as a cookie, limiting it to "/auth/refresh"
+    response.headers_mut().append(
+        "Set-Cookie",
+        cookie_header(
+            "X-AUTH-REFRESH",
+            secrets.get_refresh(),
+            "/auth/refresh",
+            true,
+            (auth.get_lifetime_end() - now).num_seconds(),
+        ),
+    );
+    // Append the Auth Token as a cookie
+    response.headers_mut().append(
+        "Set-Cookie",
+        cookie_header(
+            "X-AUTH-TOKEN",
+            secrets.get_token(),
+            "/",
+            true,
+            (auth.get_expiry() - now).num_seconds(),
+        ),
+    );
+}
+
+// Appends a new CSRF token as a cookie.
+// It's readable by scripts so it can be copied into the "X-CSRF-TOKEN" header.
+fn set_csrf_cookie(response: &mut Response<Body>, auth: &Auth) {
+    let now = chrono::Local::now().naive_local();
+    response.headers_mut().append(
+        "Set-Cookie",
+        cookie_header(
+            "X-CSRF-TOKEN",
+            &gen_random(32),
+            "/",
+            false,
+            (auth.get_lifetime_end() - now).num_seconds(),
+        ),
+    );
+}
+
+// Expires the auth and CSRF cookies on the client.
+pub fn clear_auth_cookies(response: &mut Response<Body>) {
+    response.headers_mut().append(
+        "Set-Cookie",
+        cookie_header("X-AUTH-REFRESH", "", "/auth/refresh", true, 0),
+    );
+    response.headers_mut().append(
+        "Set-Cookie",
+        cookie_header("X-AUTH-TOKEN", "", "/", true, 0),
+    );
+    response.headers_mut().append(
+        "Set-Cookie",
+        cookie_header("X-CSRF-TOKEN", "", "/", false, 0),
+    );
+}
@@ -2,30 +2,99 @@ use std::ops::Add;
 
 use crate::util::db::{can_connect, get_database};
 use crate::{error::StratError, schema::auths::dsl as auth_dsl};
-use crate::{schema::auths, util::gen_random};
+use crate::{
+    schema::{
+        access_tokens, access_tokens::dsl as access_dsl, auths, login_challenges,
+        login_challenges::dsl as challenge_dsl, login_throttles,
+        login_throttles::dsl as throttle_dsl, recovery_codes, recovery_codes::dsl as recovery_dsl,
+        used_oauth_refreshes, used_oauth_refreshes::dsl as used_oauth_dsl, used_refreshes,
+        used_refreshes::dsl as used_dsl,
+    },
+    util::{config::CONFIG, gen_random, hash_token},
+};
 use chrono::{Duration, NaiveDateTime};
 use diesel::{
-    result::Error as dsl_err, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    result::Error as dsl_err, BoolExpressionMethods, Connection, ExpressionMethods,
+    OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
 };
 
-#[derive(Queryable, Insertable, Serialize, Deserialize, Debug, AsChangeset)]
+// How long failed logins are remembered for.
+const FAILURE_WINDOW_HOURS: i64 = 24;
+// How many days an auth lasts from login, however often it's refreshed.
+const AUTH_LIFETIME_DAYS: i64 = 50;
+// How many days an OAuth grant lasts from authorization, however often it's refreshed.
+const OAUTH_LIFETIME_DAYS: i64 = 50;
+
+#[derive(Queryable, Insertable, Serialize, Deserialize, Debug, AsChangeset, Clone)]
 pub struct Auth {
     token: String,
     refresh: String,
     owner: String,
     expiry: NaiveDateTime,
     created: NaiveDateTime,
+    id: String,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    last_used: NaiveDateTime,
 }
 
 impl Auth {
     //creators
-    pub fn new(user: String) -> Self {
-        Self {
-            token: gen_random(25),
-            refresh: gen_random(33),
+    // Only hashes of the tokens are kept on the Auth, the raw values are
+    // returned alongside it and never stored.
+    pub fn new(
+        user: String,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> (Self, AuthSecrets) {
+        let secrets = AuthSecrets::new();
+        let auth = Self {
+            token: hash_token(&secrets.token),
+            refresh: hash_token(&secrets.refresh),
             owner: user,
             expiry: chrono::Local::now().naive_local() + Duration::weeks(1),
             created: chrono::Local::now().naive_local(),
+            id: gen_random(24),
+            // The column only holds 255 characters, so longer agents get cut short.
+            user_agent: user_agent.map(|ua| ua.chars().take(255).collect()),
+            ip,
+            last_used: chrono::Local::now().naive_local(),
+        };
+        (auth, secrets)
+    }
+
+    // Finds an auth using its public ID.
+    pub fn get_by_id(id: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let auth: QueryResult<Self> =
+                auth_dsl::auths.filter(auths::id.eq(id)).first::<Self>(db);
+            match auth {
+                Ok(u) => Ok(u),
+                Err(_e) => Err(StratError::UnknownSession),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Gets every auth belonging to a user that hasn't expired yet, most recently used first.
+    pub fn get_by_owner(owner: &str) -> Result<Vec<Self>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let auths: QueryResult<Vec<Self>> = auth_dsl::auths
+                .filter(auths::owner.eq(owner))
+                .order(auths::last_used.desc())
+                .load::<Self>(db);
+            match auths {
+                Ok(a) => Ok(a
+                    .into_iter()
+                    .filter(|a| a.has_expired().is_none())
+                    .collect()),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
         }
     }
 
@@ -33,7 +102,7 @@ impl Auth {
         if can_connect() {
             let db: &PgConnection = &get_database();
             let auth: QueryResult<Self> = auth_dsl::auths
-                .filter(auths::token.eq(id))
+                .filter(auths::token.eq(hash_token(id)))
                 .first::<Self>(db);
             match auth {
                 Ok(u) => match u.has_expired() {
@@ -51,7 +120,7 @@ impl Auth {
         if can_connect() {
             let db: &PgConnection = &get_database();
             let auth: QueryResult<Self> = auth_dsl::auths
-                .filter(auths::refresh.eq(id))
+                .filter(auths::refresh.eq(hash_token(id)))
                 .first::<Self>(db);
             match auth {
                 Ok(u) => match u.has_expired() {
@@ -66,22 +135,66 @@ impl Auth {
     }
 
     //getters
-    pub fn get_token(&self) -> &str {
-        &self.token
+    pub fn get_owner(&self) -> &str {
+        &self.owner
     }
 
-    pub fn get_refresh(&self) -> &str {
-        &self.refresh
+    pub fn get_id(&self) -> &str {
+        &self.id
     }
 
-    pub fn get_owner(&self) -> &str {
-        &self.owner
+    // Gets when the current token stops being valid.
+    pub fn get_expiry(&self) -> NaiveDateTime {
+        self.expiry
+    }
+
+    // Gets when this auth can no longer be refreshed.
+    pub fn get_lifetime_end(&self) -> NaiveDateTime {
+        self.created.add(Duration::days(AUTH_LIFETIME_DAYS))
+    }
+
+    // Creates a view of this auth that is safe to show to its owner.
+    // `current` is the ID of the auth making the request, so it can be flagged.
+    pub fn to_session(&self, current: &str) -> AuthSession {
+        AuthSession {
+            id: self.id.clone(),
+            user_agent: self.user_agent.clone(),
+            ip: self.ip.clone(),
+            created: self.created,
+            last_used: self.last_used,
+            expiry: self.expiry,
+            current: self.id == current,
+        }
+    }
+
+    // Records that this auth was just used.
+    // Only writes to the database once a minute to avoid an update on every request.
+    pub fn touch(&mut self) -> Option<StratError> {
+        let now = chrono::Local::now().naive_local();
+        if self.last_used + Duration::minutes(1) > now {
+            return None;
+        }
+        self.last_used = now;
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = diesel::update(auths::table.filter(auth_dsl::refresh.eq(&self.refresh)))
+                .set(auth_dsl::last_used.eq(now))
+                .execute(db);
+            match rslt {
+                Ok(_) => return None,
+                Err(e) => return Some(Self::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
     }
     //savers
     pub fn save_auth(&mut self) -> Option<StratError> {
         let db: &PgConnection = &get_database();
         if can_connect() {
-            let rslt = match Self::get_by_token(&self.token) {
+            let existing: QueryResult<Self> = auth_dsl::auths
+                .filter(auth_dsl::token.eq(&self.token))
+                .first::<Self>(db);
+            let rslt = match existing {
                 Ok(_u) => diesel::update(auths::table)
                     .set(&*self)
                     .filter(auth_dsl::token.eq(&self.token))
@@ -96,35 +209,167 @@ impl Auth {
         Some(StratError::DbFailed)
     }
 
-    pub fn refresh(&self) -> Result<Self, StratError> {
+    // Deletes this auth, consuming self.
+    pub fn delete_auth(self) -> Option<StratError> {
         if can_connect() {
             let db: &PgConnection = &get_database();
+            let rslt = diesel::delete(auths::table.filter(auth_dsl::refresh.eq(&self.refresh)))
+                .execute(db);
+            match rslt {
+                Ok(_) => return None,
+                Err(e) => return Some(Self::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Deletes every auth belonging to a user, returning how many were removed.
+    pub fn delete_all_for(owner: &str) -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            match diesel::delete(auths::table.filter(auth_dsl::owner.eq(owner))).execute(db) {
+                Ok(n) => Ok(n),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Deletes every auth belonging to a user except one, returning how many were removed.
+    pub fn delete_others_for(owner: &str, keep: &str) -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = diesel::delete(
+                auths::table.filter(auth_dsl::owner.eq(owner).and(auth_dsl::id.ne(keep))),
+            )
+            .execute(db);
+            match rslt {
+                Ok(n) => Ok(n),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Deletes every auth that can no longer be used, returning how many were removed.
+    // An expired token can still be refreshed until the absolute lifetime ends,
+    // so only auths past that are dead, whatever their expiry says.
+    pub fn delete_expired() -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let cutoff = chrono::Local::now().naive_local() - Duration::days(AUTH_LIFETIME_DAYS);
+            let rslt =
+                diesel::delete(auths::table.filter(auth_dsl::created.lt(cutoff))).execute(db);
+            match rslt {
+                Ok(n) => Ok(n),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Deletes the rotated refreshes of sessions that no longer exist, returning how many were removed.
+    pub fn delete_orphaned_refreshes() -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = diesel::delete(
+                used_refreshes::table
+                    .filter(used_dsl::family.ne_all(auth_dsl::auths.select(auth_dsl::id))),
+            )
+            .execute(db);
+            match rslt {
+                Ok(n) => Ok(n),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Rotates a refresh token, issuing a new token and refresh for the same session.
+    // The old refresh is remembered as part of the session's family, presenting it
+    // again means it has leaked, so the whole family is revoked.
+    pub fn refresh(refresh: &str) -> Result<(Self, AuthSecrets), StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let refresh = hash_token(refresh);
             let auth: QueryResult<Self> = auth_dsl::auths
-                .filter(auths::refresh.eq(self.refresh.clone()))
+                .filter(auths::refresh.eq(&refresh))
                 .first::<Self>(db);
             let mut u = match auth {
-                Ok(mut u) => {
-                    u.token = gen_random(25);
-                    u.expiry = chrono::Local::now().naive_local() + Duration::weeks(1);
-                    match u.has_expired() {
-                        Some(e) => return Err(e),
-                        None => u,
-                    }
+                Ok(u) => u,
+                Err(_e) => return Err(Self::check_reuse(&refresh)),
+            };
+            // Only the absolute lifetime matters here, an expired token is why we refresh.
+            if u.get_lifetime_end() < chrono::Local::now().naive_local() {
+                return Err(StratError::AuthExpired);
+            }
+            let secrets = AuthSecrets::new();
+            u.token = hash_token(&secrets.token);
+            u.refresh = hash_token(&secrets.refresh);
+            u.expiry = chrono::Local::now().naive_local() + Duration::weeks(1);
+            // Filtering on the old refresh makes this a compare-and-swap,
+            // if another request rotated it first, nothing is updated.
+            let rslt = diesel::update(auths::table.filter(auth_dsl::refresh.eq(&refresh)))
+                .set(&u)
+                .execute(db);
+            match rslt {
+                Ok(0) => {
+                    Self::revoke_family(&u.id);
+                    return Err(StratError::RefreshReused);
                 }
-                Err(_e) => return Err(StratError::UnknownRefresh),
+                Ok(_) => {}
+                Err(e) => return Err(Self::match_errors(e)),
+            }
+            let used = UsedRefresh {
+                refresh,
+                family: u.id.clone(),
+                rotated: chrono::Local::now().naive_local(),
             };
-            u.save_auth().unwrap();
-            Ok(u)
+            match diesel::insert_into(used_refreshes::table)
+                .values(&used)
+                .execute(db)
+            {
+                Ok(_) => Ok((u, secrets)),
+                Err(e) => Err(Self::match_errors(e)),
+            }
         } else {
             Err(StratError::DbFailed)
         }
     }
 
+    // Works out why a (hashed) refresh couldn't be found.
+    // If it was already rotated out, its family is revoked.
+    fn check_reuse(refresh: &str) -> StratError {
+        let db: &PgConnection = &get_database();
+        let used: QueryResult<UsedRefresh> = used_dsl::used_refreshes
+            .find(refresh)
+            .first::<UsedRefresh>(db);
+        match used {
+            Ok(used) => {
+                Self::revoke_family(&used.family);
+                StratError::RefreshReused
+            }
+            Err(_e) => StratError::UnknownRefresh,
+        }
+    }
+
+    // Deletes the auth a family belongs to, its used refreshes are removed with it.
+    fn revoke_family(family: &str) {
+        let db: &PgConnection = &get_database();
+        if let Err(e) = diesel::delete(auths::table.filter(auth_dsl::id.eq(family))).execute(db) {
+            eprintln!("Failed to revoke token family {}: {}", family, e);
+        }
+    }
+
     //Utils
     pub fn has_expired(&self) -> Option<StratError> {
         if self.expiry < chrono::Local::now().naive_local() {
             return Some(StratError::TokenExpired);
-        } else if self.created.add(Duration::days(50)) < chrono::Local::now().naive_local() {
+        } else if self.get_lifetime_end() < chrono::Local::now().naive_local() {
             return Some(StratError::AuthExpired);
         }
         None
@@ -140,6 +385,60 @@ impl Auth {
     }
 }
 
+// The raw token and refresh of an Auth.
+// These only ever live in the client's cookies, the database holds their hashes.
+pub struct AuthSecrets {
+    token: String,
+    refresh: String,
+}
+
+impl AuthSecrets {
+    fn new() -> Self {
+        Self {
+            token: gen_random(25),
+            refresh: gen_random(33),
+        }
+    }
+
+    pub fn get_token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn get_refresh(&self) -> &str {
+        &self.refresh
+    }
+}
+
+// A refresh token that has been rotated out of the session (family) it belonged to.
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "used_refreshes"]
+struct UsedRefresh {
+    refresh: String,
+    family: String,
+    rotated: NaiveDateTime,
+}
+
+// A refresh that has been rotated out of the OAuth token it belonged to.
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "used_oauth_refreshes"]
+struct UsedOAuthRefresh {
+    refresh: String,
+    token_id: String,
+    rotated: NaiveDateTime,
+}
+
+// The parts of an Auth that can be shown to a user, without any tokens.
+#[derive(Serialize, Debug)]
+pub struct AuthSession {
+    id: String,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created: NaiveDateTime,
+    last_used: NaiveDateTime,
+    expiry: NaiveDateTime,
+    current: bool,
+}
+
 pub struct AuthRefresh {
     refresh: String,
 }
@@ -156,6 +455,11 @@ impl AuthRefresh {
     pub fn to_auth(&self) -> Result<Auth, StratError> {
         Auth::get_by_refresh(&self.refresh)
     }
+
+    // Rotates this refresh, returning the updated Auth and its new secrets.
+    pub fn rotate(&self) -> Result<(Auth, AuthSecrets), StratError> {
+        Auth::refresh(&self.refresh)
+    }
 }
 
 pub struct AuthToken {
@@ -174,3 +478,614 @@ impl AuthToken {
         Auth::get_by_token(&self.token)
     }
 }
+
+// Proof that a password was accepted for an account that still needs a second factor.
+#[derive(Queryable, Insertable, Debug)]
+pub struct LoginChallenge {
+    token: String,
+    owner: String,
+    expiry: NaiveDateTime,
+    created: NaiveDateTime,
+}
+
+impl LoginChallenge {
+    // Creates and saves a challenge, returning the raw token for the client.
+    pub fn create(owner: String) -> Result<String, StratError> {
+        let token = gen_random(40);
+        let challenge = Self {
+            token: hash_token(&token),
+            owner,
+            expiry: chrono::Local::now().naive_local() + Duration::minutes(5),
+            created: chrono::Local::now().naive_local(),
+        };
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            match diesel::insert_into(login_challenges::table)
+                .values(&challenge)
+                .execute(db)
+            {
+                Ok(_) => Ok(token),
+                Err(e) => Err(Auth::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Uses up a challenge, returning the ID of the user it belongs to.
+    // A challenge only gets one attempt, so codes can't be guessed without the password.
+    pub fn consume(token: &str) -> Result<String, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let challenge: QueryResult<Self> = diesel::delete(
+                challenge_dsl::login_challenges.filter(challenge_dsl::token.eq(hash_token(token))),
+            )
+            .get_result::<Self>(db);
+            match challenge {
+                Ok(c) if c.expiry >= chrono::Local::now().naive_local() => Ok(c.owner),
+                _ => Err(StratError::UnknownChallenge),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+}
+
+// A hashed single-use code that can stand in for a two-factor code.
+#[derive(Queryable, Insertable, Debug)]
+pub struct RecoveryCode {
+    owner: String,
+    code: String,
+}
+
+impl RecoveryCode {
+    // Replaces every recovery code of a user with new ones.
+    pub fn replace_for(owner: &str, codes: &[String]) -> Option<StratError> {
+        let rows: Vec<Self> = codes
+            .iter()
+            .map(|c| Self {
+                owner: owner.to_owned(),
+                code: hash_token(c),
+            })
+            .collect();
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                diesel::delete(recovery_dsl::recovery_codes.filter(recovery_dsl::owner.eq(owner)))
+                    .execute(db)?;
+                diesel::insert_into(recovery_codes::table)
+                    .values(&rows)
+                    .execute(db)
+            });
+            match rslt {
+                Ok(_) => return None,
+                Err(e) => return Some(Auth::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Uses up a recovery code, returning whether it was valid.
+    pub fn consume(owner: &str, code: &str) -> Result<bool, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = diesel::delete(
+                recovery_dsl::recovery_codes
+                    .filter(recovery_dsl::owner.eq(owner))
+                    .filter(recovery_dsl::code.eq(hash_token(code.trim()))),
+            )
+            .execute(db);
+            match rslt {
+                Ok(n) => Ok(n > 0),
+                Err(e) => Err(Auth::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Deletes every recovery code of a user.
+    pub fn delete_for(owner: &str) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt =
+                diesel::delete(recovery_dsl::recovery_codes.filter(recovery_dsl::owner.eq(owner)))
+                    .execute(db);
+            match rslt {
+                Ok(_) => return None,
+                Err(e) => return Some(Auth::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+}
+
+// Failed login tracking for either an account or a source IP.
+#[derive(Queryable, Insertable, Debug)]
+pub struct LoginThrottle {
+    scope: String,
+    key: String,
+    failures: i32,
+    locked_until: Option<NaiveDateTime>,
+    last_failure: NaiveDateTime,
+}
+
+impl LoginThrottle {
+    // Checks if an IP or account is locked out, returning how long is left if so.
+    // This happens before the password is checked, so lockouts also save us the argon2 work.
+    pub fn check(ip: &str, email: &str) -> Option<StratError> {
+        let now = chrono::Local::now().naive_local();
+        for (scope, key) in Self::keys(ip, email).iter() {
+            match Self::get(scope, key) {
+                Ok(Some(t)) => match t.locked_until {
+                    Some(until) if until > now => {
+                        return Some(StratError::LoginLocked((until - now).num_seconds() + 1))
+                    }
+                    _ => {}
+                },
+                Ok(None) => {}
+                Err(e) => return Some(e),
+            }
+        }
+        None
+    }
+
+    // Records a failed login against an IP and account, locking them out once they
+    // pass their limit. Every failure past the limit doubles the lockout.
+    pub fn record_failure(ip: &str, email: &str) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            for (scope, key) in Self::keys(ip, email).iter() {
+                if let Err(e) = Self::count_failure(db, scope, key) {
+                    return Some(Auth::match_errors(e));
+                }
+            }
+            return None;
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Forgets the failures of an account after it logs in successfully.
+    pub fn clear(email: &str) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = diesel::delete(
+                throttle_dsl::login_throttles
+                    .filter(throttle_dsl::scope.eq("account"))
+                    .filter(throttle_dsl::key.eq(Self::normalize(email))),
+            )
+            .execute(db);
+            match rslt {
+                Ok(_) => return None,
+                Err(e) => return Some(Auth::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    fn get(scope: &str, key: &str) -> Result<Option<Self>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let throttle: QueryResult<Option<Self>> = throttle_dsl::login_throttles
+                .find((scope, key))
+                .first::<Self>(db)
+                .optional();
+            match throttle {
+                Ok(t) => Ok(t),
+                Err(e) => Err(Auth::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Counts one failure, the increment happens in the database so
+    // concurrent failures can't overwrite each other's counts.
+    fn count_failure(db: &PgConnection, scope: &str, key: &str) -> Result<(), dsl_err> {
+        let now = chrono::Local::now().naive_local();
+        // Old failures are forgotten so occasional typos never add up to a lockout.
+        diesel::update(
+            throttle_dsl::login_throttles
+                .find((scope, key))
+                .filter(throttle_dsl::last_failure.lt(now - Duration::hours(FAILURE_WINDOW_HOURS))),
+        )
+        .set(throttle_dsl::failures.eq(0))
+        .execute(db)?;
+        let first = Self {
+            scope: scope.to_owned(),
+            key: key.to_owned(),
+            failures: 1,
+            locked_until: None,
+            last_failure: now,
+        };
+        let t: Self = diesel::insert_into(login_throttles::table)
+            .values(&first)
+            .on_conflict((throttle_dsl::scope, throttle_dsl::key))
+            .do_update()
+            .set((
+                throttle_dsl::failures.eq(throttle_dsl::failures + 1),
+                throttle_dsl::last_failure.eq(now),
+            ))
+            .get_result(db)?;
+        let limit = if scope == "ip" {
+            CONFIG.login_ip_max_failures
+        } else {
+            CONFIG.login_max_failures
+        };
+        if t.failures >= limit {
+            let doublings = (t.failures - limit).min(20) as u32;
+            let secs =
+                (CONFIG.login_lockout_base * 2i64.pow(doublings)).min(CONFIG.login_lockout_max);
+            // Only the highest count sets the lockout, so a slower request can't shorten it.
+            diesel::update(
+                throttle_dsl::login_throttles
+                    .find((scope, key))
+                    .filter(throttle_dsl::failures.eq(t.failures)),
+            )
+            .set(throttle_dsl::locked_until.eq(now + Duration::seconds(secs)))
+            .execute(db)?;
+        }
+        Ok(())
+    }
+
+    fn keys(ip: &str, email: &str) -> [(&'static str, String); 2] {
+        [("ip", ip.to_owned()), ("account", Self::normalize(email))]
+    }
+
+    fn normalize(email: &str) -> String {
+        email.trim().to_lowercase()
+    }
+}
+
+// Every scope an access token can be granted.
+pub const SCOPES: [&str; 4] = ["follows:write", "posts:read", "posts:write", "profile:read"];
+// Access tokens start with this, so they can be told apart from session tokens.
+pub const ACCESS_TOKEN_PREFIX: &str = "strat_";
+
+// What the authenticated request is allowed to do.
+// Sessions can do everything, access tokens only what they were granted.
+#[derive(Clone, Debug)]
+pub enum Scopes {
+    All,
+    Only(Vec<String>),
+}
+
+impl Scopes {
+    pub fn allows(&self, scope: &str) -> bool {
+        match self {
+            Scopes::All => true,
+            Scopes::Only(scopes) => scopes.iter().any(|s| s == scope),
+        }
+    }
+
+    // Returns an Error unless the scope is allowed.
+    pub fn require(&self, scope: &str) -> Result<(), StratError> {
+        match self.allows(scope) {
+            true => Ok(()),
+            false => Err(StratError::MissingScope(scope.to_owned())),
+        }
+    }
+}
+
+// A named, long-lived token a user can give to bots and integrations.
+#[derive(Queryable, Insertable, Serialize, Debug, Clone)]
+pub struct AccessToken {
+    id: String,
+    owner: String,
+    name: String,
+    #[serde(skip)]
+    token: String,
+    scopes: Vec<String>,
+    created: NaiveDateTime,
+    last_used: Option<NaiveDateTime>,
+    expiry: Option<NaiveDateTime>,
+    // Only set for tokens issued to OAuth clients.
+    client_id: Option<String>,
+    #[serde(skip)]
+    refresh: Option<String>,
+}
+
+impl AccessToken {
+    // Creates a new access token but doesn't save it.
+    // Only the hash of the token is kept, the raw token is returned to be shown once.
+    pub fn new(
+        owner: String,
+        name: String,
+        scopes: Vec<String>,
+        expiry: Option<NaiveDateTime>,
+    ) -> Result<(Self, String), StratError> {
+        if let Some(s) = scopes.iter().find(|s| !SCOPES.contains(&s.as_str())) {
+            return Err(StratError::UnknownScope(s.to_owned()));
+        }
+        let token = format!("{}{}", ACCESS_TOKEN_PREFIX, gen_random(40));
+        let access = Self {
+            id: gen_random(24),
+            owner,
+            name,
+            token: hash_token(&token),
+            scopes,
+            created: chrono::Local::now().naive_local(),
+            last_used: None,
+            expiry,
+            client_id: None,
+            refresh: None,
+        };
+        Ok((access, token))
+    }
+
+    // Creates a new token for an OAuth client, which expires after an hour and
+    // comes with a refresh. Returns the raw access token and refresh.
+    pub fn new_oauth(
+        owner: String,
+        client_id: String,
+        name: String,
+        scopes: Vec<String>,
+    ) -> (Self, String, String) {
+        let token = format!("{}{}", ACCESS_TOKEN_PREFIX, gen_random(40));
+        let refresh = gen_random(48);
+        let access = Self {
+            id: gen_random(24),
+            owner,
+            name,
+            token: hash_token(&token),
+            scopes,
+            created: chrono::Local::now().naive_local(),
+            last_used: None,
+            expiry: Some(chrono::Local::now().naive_local() + Duration::hours(1)),
+            client_id: Some(client_id),
+            refresh: Some(hash_token(&refresh)),
+        };
+        (access, token, refresh)
+    }
+
+    // Issues a new access token and refresh for an OAuth client's refresh.
+    // Filtering on the old refresh means it can only ever be used once, presenting
+    // it again means it has leaked, so the token it belonged to is revoked.
+    pub fn refresh_oauth(
+        client_id: &str,
+        refresh: &str,
+    ) -> Result<(Self, String, String), StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let old = hash_token(refresh);
+            let access: QueryResult<Self> = access_dsl::access_tokens
+                .filter(access_dsl::refresh.eq(&old))
+                .filter(access_dsl::client_id.eq(client_id))
+                .first::<Self>(db);
+            let mut access = match access {
+                Ok(a) => a,
+                Err(_e) => return Err(Self::check_oauth_reuse(&old)),
+            };
+            let now = chrono::Local::now().naive_local();
+            let lifetime_end = access.created + Duration::days(OAUTH_LIFETIME_DAYS);
+            if lifetime_end < now {
+                // The grant is over, so the client has to ask the user again.
+                if let Some(e) = access.delete_token() {
+                    return Err(e);
+                }
+                return Err(StratError::InvalidGrant);
+            }
+            let token = format!("{}{}", ACCESS_TOKEN_PREFIX, gen_random(40));
+            let new_refresh = gen_random(48);
+            access.token = hash_token(&token);
+            access.refresh = Some(hash_token(&new_refresh));
+            access.expiry = Some((now + Duration::hours(1)).min(lifetime_end));
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                let updated =
+                    diesel::update(access_dsl::access_tokens.filter(access_dsl::refresh.eq(&old)))
+                        .set((
+                            access_dsl::token.eq(&access.token),
+                            access_dsl::refresh.eq(&access.refresh),
+                            access_dsl::expiry.eq(&access.expiry),
+                        ))
+                        .execute(db)?;
+                if updated == 1 {
+                    let used = UsedOAuthRefresh {
+                        refresh: old.clone(),
+                        token_id: access.id.clone(),
+                        rotated: now,
+                    };
+                    diesel::insert_into(used_oauth_refreshes::table)
+                        .values(&used)
+                        .execute(db)?;
+                }
+                Ok(updated)
+            });
+            match rslt {
+                Ok(1) => Ok((access, token, new_refresh)),
+                // Another request rotated it first, so it was presented twice.
+                Ok(_) => Err(Self::check_oauth_reuse(&old)),
+                Err(e) => Err(Auth::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Works out why a (hashed) OAuth refresh couldn't be used.
+    // If it was already rotated out, the token it belonged to is revoked.
+    fn check_oauth_reuse(refresh: &str) -> StratError {
+        let db: &PgConnection = &get_database();
+        let used: QueryResult<UsedOAuthRefresh> = used_oauth_dsl::used_oauth_refreshes
+            .find(refresh)
+            .first::<UsedOAuthRefresh>(db);
+        match used {
+            Ok(used) => {
+                if let Err(e) =
+                    diesel::delete(access_dsl::access_tokens.find(&used.token_id)).execute(db)
+                {
+                    eprintln!("Failed to revoke OAuth token {}: {}", used.token_id, e);
+                }
+                StratError::RefreshReused
+            }
+            Err(_e) => StratError::InvalidGrant,
+        }
+    }
+
+    // Finds a client's token using either the raw access token or refresh.
+    pub fn get_for_client(client_id: &str, token: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let hashed = hash_token(token);
+            let access: QueryResult<Self> = access_dsl::access_tokens
+                .filter(access_dsl::client_id.eq(client_id))
+                .filter(
+                    access_dsl::token
+                        .eq(&hashed)
+                        .or(access_dsl::refresh.eq(&hashed)),
+                )
+                .first::<Self>(db);
+            match access {
+                Ok(a) => Ok(a),
+                Err(_e) => Err(StratError::UnknownAccessToken),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    pub fn get_by_token(token: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let access: QueryResult<Self> = access_dsl::access_tokens
+                .filter(access_dsl::token.eq(hash_token(token)))
+                .first::<Self>(db);
+            match access {
+                Ok(a) => match a.expiry {
+                    Some(expiry) if expiry < chrono::Local::now().naive_local() => {
+                        Err(StratError::TokenExpired)
+                    }
+                    _ => Ok(a),
+                },
+                Err(_e) => Err(StratError::UnknownToken),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    pub fn get_by_id(id: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let access: QueryResult<Self> = access_dsl::access_tokens.find(id).first::<Self>(db);
+            match access {
+                Ok(a) => Ok(a),
+                Err(_e) => Err(StratError::UnknownAccessToken),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Gets every access token belonging to a user, newest first.
+    pub fn get_by_owner(owner: &str) -> Result<Vec<Self>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let access: QueryResult<Vec<Self>> = access_dsl::access_tokens
+                .filter(access_dsl::owner.eq(owner))
+                .order(access_dsl::created.desc())
+                .load::<Self>(db);
+            match access {
+                Ok(a) => Ok(a),
+                Err(e) => Err(Auth::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    pub fn save_token(&self) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            match diesel::insert_into(access_tokens::table)
+                .values(self)
+                .execute(db)
+            {
+                Ok(_) => return None,
+                Err(e) => return Some(Auth::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Deletes every access token belonging to a user, returning how many were removed.
+    pub fn delete_all_for(owner: &str) -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            match diesel::delete(access_tokens::table.filter(access_dsl::owner.eq(owner)))
+                .execute(db)
+            {
+                Ok(n) => Ok(n),
+                Err(e) => Err(Auth::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Deletes this access token, consuming self.
+    pub fn delete_token(self) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            match diesel::delete(access_dsl::access_tokens.find(&self.id)).execute(db) {
+                Ok(_) => return None,
+                Err(e) => return Some(Auth::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Records that this token was just used, at most once a minute.
+    pub fn touch(&mut self) -> Option<StratError> {
+        let now = chrono::Local::now().naive_local();
+        if let Some(last) = self.last_used {
+            if last + Duration::minutes(1) > now {
+                return None;
+            }
+        }
+        self.last_used = Some(now);
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = diesel::update(access_dsl::access_tokens.find(&self.id))
+                .set(access_dsl::last_used.eq(now))
+                .execute(db);
+            match rslt {
+                Ok(_) => return None,
+                Err(e) => return Some(Auth::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn to_scopes(&self) -> Scopes {
+        Scopes::Only(self.scopes.clone())
+    }
+
+    pub fn get_scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    pub fn get_created(&self) -> NaiveDateTime {
+        self.created
+    }
+
+    pub fn get_expiry(&self) -> Option<NaiveDateTime> {
+        self.expiry
+    }
+
+    // Checks if this token can still be used to access the API.
+    pub fn is_active(&self) -> bool {
+        match self.expiry {
+            Some(expiry) => expiry >= chrono::Local::now().naive_local(),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// RFC 6238 time-based one-time passwords, as used by authenticator apps.
+use crate::util::gen_random;
+use hmac::{Hmac, Mac, NewMac};
+use rand::{thread_rng, RngCore};
+use sha1::Sha1;
+
+// How many seconds each code is valid for.
+const PERIOD: i64 = 30;
+// How many digits each code has.
+const DIGITS: u32 = 6;
+// How many periods either side of now are accepted, to allow for clock drift.
+const SKEW: i64 = 1;
+
+// Generates a new base32 encoded secret.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    thread_rng().fill_bytes(&mut secret);
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+}
+
+// Creates the otpauth URI authenticator apps use to enroll a secret.
+pub fn provisioning_uri(secret: &str, account: &str) -> String {
+    let label: String = form_urlencoded::byte_serialize(account.as_bytes()).collect();
+    format!(
+        "otpauth://totp/Stratosphere:{}?secret={}&issuer=Stratosphere&algorithm=SHA1&digits={}&period={}",
+        label, secret, DIGITS, PERIOD
+    )
+}
+
+// Checks a code against a secret, returning the time step it matched.
+// Steps at or before `last_step` are rejected so a code can't be replayed.
+pub fn verify(secret: &str, code: &str, last_step: i64) -> Option<i64> {
+    let key = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let now = chrono::Utc::now().timestamp() / PERIOD;
+    (now - SKEW..=now + SKEW)
+        .filter(|step| *step > last_step)
+        .find(|step| hotp(&key, *step as u64) == code)
+}
+
+// Generates a set of single-use recovery codes.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..10).map(|_| gen_random(10)).collect()
+}
+
+// RFC 4226 HMAC-based one-time password for a counter.
+fn hotp(key: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_varkey(key).expect("HMAC can take a key of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
@@ -3,6 +3,8 @@ use std::{error::Error, fmt::Display};
 pub enum StratError {
     // Database Errors
     DbFailed,
+    // Mail Errors
+    MailFailed,
     // User Errors
     UserNotFound,
     EmailInUse,
@@ -10,6 +12,24 @@ pub enum StratError {
     NameExists,
     Unknown,
     BadLogin,
+    LoginLocked(i64),
+    AccountSuspended(Option<chrono::NaiveDateTime>, Option<String>),
+    AccountBanned(Option<String>),
+    AccountDeactivated,
+    UnknownReset,
+    ResetExpired,
+    UnknownVerification,
+    VerificationExpired,
+    EmailUnverified,
+    PasswordTooShort(usize),
+    PasswordCommon,
+    PasswordPersonal,
+    WrongPassword,
+    InvalidNickname,
+    NicknameCooldown(i64),
+    InvalidWebsite,
+    UnknownExport,
+    ExportNotReady,
     // Auth Errors
     AuthFailed,
     UnknownToken,
@@ -18,13 +38,46 @@ pub enum StratError {
     UnknownRefresh,
     InvalidToken,
     InvalidRefresh,
+    UnknownSession,
+    RefreshReused,
+    CsrfFailed,
+    UnknownChallenge,
+    BadTotp,
+    TotpEnabled,
+    TotpDisabled,
+    SessionRequired,
+    MissingScope(String),
+    UnknownScope(String),
+    UnknownAccessToken,
+    InvalidTokenLifetime(i64),
+    // OAuth Errors
+    UnknownClient,
+    InvalidClient,
+    BadRedirect,
+    InvalidGrant,
+    UnsupportedGrant,
+    InvalidPkce,
+    AccessDenied,
+    // Media Errors
+    StorageFailed,
+    BadImage,
+    ImageTooSmall(u32, u32),
+    ImageTooLarge(u32),
     // Multipart
     BadMulti,
     OversizedField(String, u64),
     MediaUnsupported,
+    // Follow Errors
+    FollowSelf,
+    UnknownFollowRequest,
+    ProtectedUser,
     // Post Errors
     UnknownPost,
     NoPermission,
+    MissingPermission(String),
+    TargetOutranks,
+    UnknownRole(String),
+    InvalidSuspension(i64),
     NeedsContent,
     // This Error is for testing only!
     Custom(String),
@@ -35,6 +88,9 @@ impl Display for StratError {
             StratError::DbFailed => {
                 write!(f, "A server error has occured! Please try again later.")
             }
+            StratError::MailFailed => {
+                write!(f, "An email could not be sent! Please try again later.")
+            }
             StratError::UserNotFound => write!(f, "The requested user could not be found."),
             StratError::EmailInUse => write!(f, "The requested email is already in use!"),
             StratError::UniqueExists => write!(
@@ -44,6 +100,75 @@ impl Display for StratError {
             StratError::NameExists => write!(f, "The requested username is already in use."),
             StratError::Unknown => write!(f, "An unknown error has occured!"),
             StratError::BadLogin => write!(f, "The Email or Password submitted is invalid!"),
+            StratError::LoginLocked(secs) => write!(
+                f,
+                "Too many failed login attempts! Please try again in {} seconds.",
+                secs
+            ),
+            StratError::AccountSuspended(until, reason) => {
+                match until {
+                    Some(until) => write!(f, "This account is suspended until {} UTC.", until)?,
+                    None => write!(f, "This account is suspended.")?,
+                }
+                match reason {
+                    Some(reason) => write!(f, " Reason: {}", reason),
+                    None => Ok(()),
+                }
+            }
+            StratError::AccountBanned(reason) => match reason {
+                Some(reason) => write!(f, "This account has been banned. Reason: {}", reason),
+                None => write!(f, "This account has been banned."),
+            },
+            StratError::AccountDeactivated => write!(f, "This account has been deactivated."),
+            StratError::UnknownReset => write!(
+                f,
+                "The password reset token provided is invalid or has already been used."
+            ),
+            StratError::ResetExpired => {
+                write!(f, "The password reset token provided has expired!")
+            }
+            StratError::UnknownVerification => write!(
+                f,
+                "The verification token provided is invalid or has already been used."
+            ),
+            StratError::VerificationExpired => {
+                write!(f, "The verification token provided has expired!")
+            }
+            StratError::EmailUnverified => {
+                write!(f, "Please verify your email address before doing this.")
+            }
+            StratError::PasswordTooShort(len) => {
+                write!(f, "Passwords must be at least {} characters long.", len)
+            }
+            StratError::PasswordCommon => write!(
+                f,
+                "This password is too common, please choose a different one."
+            ),
+            StratError::PasswordPersonal => {
+                write!(f, "Passwords can't contain your email address or nickname.")
+            }
+            StratError::WrongPassword => {
+                write!(f, "The current password submitted is incorrect!")
+            }
+            StratError::InvalidNickname => write!(
+                f,
+                "Nicknames must be 3 to 32 letters, numbers or underscores, and not reserved."
+            ),
+            StratError::NicknameCooldown(days) => write!(
+                f,
+                "Your nickname was changed recently! You can change it again in {} days.",
+                days
+            ),
+            StratError::InvalidWebsite => {
+                write!(f, "Websites must be a http or https link.")
+            }
+            StratError::UnknownExport => {
+                write!(f, "The requested data export could not be found.")
+            }
+            StratError::ExportNotReady => write!(
+                f,
+                "The requested data export isn't ready yet, or has expired."
+            ),
             StratError::UnknownToken => {
                 write!(f, "The Token provided could not be linked to a session!")
             }
@@ -64,6 +189,81 @@ impl Display for StratError {
             StratError::InvalidRefresh => {
                 write!(f, "The Refresh Token provided is malformed or missing.")
             }
+            StratError::UnknownSession => {
+                write!(f, "The requested session could not be found.")
+            }
+            StratError::RefreshReused => write!(
+                f,
+                "The Refresh Token provided has already been used, the session or token it belonged to has been revoked."
+            ),
+            StratError::CsrfFailed => {
+                write!(f, "The CSRF Token provided is missing or does not match.")
+            }
+            StratError::UnknownChallenge => write!(
+                f,
+                "The login challenge provided is invalid or has expired, please log in again."
+            ),
+            StratError::BadTotp => {
+                write!(f, "The two-factor code provided is invalid!")
+            }
+            StratError::TotpEnabled => {
+                write!(f, "Two-factor authentication is already enabled.")
+            }
+            StratError::TotpDisabled => {
+                write!(f, "Two-factor authentication is not enabled.")
+            }
+            StratError::SessionRequired => write!(
+                f,
+                "This can only be done while logged in, access tokens are not allowed."
+            ),
+            StratError::MissingScope(scope) => {
+                write!(f, "The access token provided lacks the scope: {}", scope)
+            }
+            StratError::UnknownScope(scope) => {
+                write!(f, "The scope: {} does not exist.", scope)
+            }
+            StratError::UnknownAccessToken => {
+                write!(f, "The requested access token could not be found.")
+            }
+            StratError::InvalidTokenLifetime(max) => {
+                write!(f, "Access tokens must last between 1 and {} days.", max)
+            }
+            StratError::UnknownClient => {
+                write!(f, "The requested OAuth client could not be found.")
+            }
+            StratError::InvalidClient => {
+                write!(f, "The OAuth client could not be authenticated.")
+            }
+            StratError::BadRedirect => write!(
+                f,
+                "The redirect URI provided is not registered for this client."
+            ),
+            StratError::InvalidGrant => write!(
+                f,
+                "The authorization code or refresh token provided is invalid or has expired."
+            ),
+            StratError::UnsupportedGrant => {
+                write!(f, "The grant type requested is not supported.")
+            }
+            StratError::InvalidPkce => write!(
+                f,
+                "A valid S256 code challenge or code verifier must be provided."
+            ),
+            StratError::AccessDenied => write!(f, "The user denied the authorization request."),
+            StratError::StorageFailed => {
+                write!(f, "A file could not be stored! Please try again later.")
+            }
+            StratError::BadImage => write!(f, "The image provided could not be read."),
+            StratError::ImageTooSmall(width, height) => write!(
+                f,
+                "The image provided must be at least {}x{} pixels.",
+                width, height
+            ),
+            StratError::ImageTooLarge(max) => write!(
+                f,
+                "The image provided can't be more than {} pixels in either direction.",
+                max
+            ),
             StratError::BadMulti => {
                 write!(f, "This request must be a valid Multipart Request")
             }
@@ -80,12 +280,35 @@ impl Display for StratError {
                     "The Multipart Request contains an unsupported media type!"
                 )
             }
+            StratError::FollowSelf => write!(f, "You can't follow yourself!"),
+            StratError::UnknownFollowRequest => {
+                write!(f, "The requested Follow Request could not be found.")
+            }
+            StratError::ProtectedUser => {
+                write!(
+                    f,
+                    "This user is protected, only their followers can see this."
+                )
+            }
             StratError::UnknownPost => {
                 write!(f, "The requested Post could not be found.")
             }
             StratError::NoPermission => {
                 write!(f, "The Authenticated User is not the owner of this post.")
             }
+            StratError::MissingPermission(permission) => {
+                write!(f, "This requires the permission: {}", permission)
+            }
+            StratError::TargetOutranks => write!(
+                f,
+                "This can't be done to users with the same or a higher role than you."
+            ),
+            StratError::UnknownRole(role) => {
+                write!(f, "The role: {} does not exist.", role)
+            }
+            StratError::InvalidSuspension(max) => {
+                write!(f, "Suspensions must last between 1 and {} days.", max)
+            }
             StratError::Custom(val) => {
                 write!(f, "{}", val)
             }
@@ -0,0 +1,123 @@
+use super::structure::{Follow, FollowRequest};
+use crate::{
+    auth::{routes::get_viewer, structure::Scopes},
+    error::StratError,
+    user::structure::User,
+    util::{get_page, json_response},
+};
+use chrono::NaiveDateTime;
+use hyper::{Body, Request, Response};
+use routerify::ext::RequestExt;
+
+// Makes the authenticated user follow someone.
+pub async fn follow_user(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    req.context::<Scopes>().unwrap().require("follows:write")?;
+    let user = req.context::<User>().unwrap();
+    let target = User::get_user(req.param("id").unwrap())?;
+    // Accounts that aren't active can't gain followers.
+    if target.are_posts_hidden() {
+        return Err(StratError::UserNotFound);
+    }
+    // Protected users have to approve their followers first.
+    let response = if target.is_protected() {
+        if Follow::is_following(user.get_id(), target.get_id())? {
+            "Already following user."
+        } else if FollowRequest::create(user.get_id(), target.get_id())? {
+            "Follow request sent!"
+        } else {
+            "Follow request already sent."
+        }
+    } else if Follow::create(user.get_id(), target.get_id())? {
+        "Successfully followed user!"
+    } else {
+        "Already following user."
+    };
+    Ok(json_response(json!({"status": 200, "response": response})))
+}
+
+// Makes the authenticated user stop following someone, or take back their request to.
+pub async fn unfollow_user(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    req.context::<Scopes>().unwrap().require("follows:write")?;
+    let user = req.context::<User>().unwrap();
+    let target = req.param("id").unwrap();
+    let response = if Follow::delete(user.get_id(), target)? {
+        "Successfully unfollowed user!"
+    } else if FollowRequest::delete(user.get_id(), target)? {
+        "Follow request cancelled."
+    } else {
+        "Not following user."
+    };
+    Ok(json_response(json!({"status": 200, "response": response})))
+}
+
+// Lists the users following someone, 50 at a time.
+// Takes the page as a query parameter ex: /user/id/ABCDEFG.../followers?page=0
+pub async fn list_followers(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let target = User::get_user(req.param("id").unwrap())?;
+    require_visible(&req, &target)?;
+    let follows = Follow::get_followers(target.get_id(), get_page(&req))?;
+    Ok(json_response(
+        json!({"status": 200, "response": to_entries(follows)}),
+    ))
+}
+
+// Lists the users someone follows, 50 at a time.
+// Takes the page as a query parameter ex: /user/id/ABCDEFG.../following?page=0
+pub async fn list_following(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let target = User::get_user(req.param("id").unwrap())?;
+    require_visible(&req, &target)?;
+    let follows = Follow::get_following(target.get_id(), get_page(&req))?;
+    Ok(json_response(
+        json!({"status": 200, "response": to_entries(follows)}),
+    ))
+}
+
+// Lists the users asking to follow the authenticated user, 50 at a time, oldest first.
+// Takes the page as a query parameter ex: /v1/me/follow-requests?page=0
+pub async fn list_follow_requests(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    req.context::<Scopes>().unwrap().require("follows:write")?;
+    let user = req.context::<User>().unwrap();
+    let requests = FollowRequest::get_for(user.get_id(), get_page(&req))?;
+    Ok(json_response(
+        json!({"status": 200, "response": to_entries(requests)}),
+    ))
+}
+
+// Lets someone follow the authenticated user, taking the requester's ID as the path.
+pub async fn approve_follow_request(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    req.context::<Scopes>().unwrap().require("follows:write")?;
+    let user = req.context::<User>().unwrap();
+    if !FollowRequest::approve(req.param("id").unwrap(), user.get_id())? {
+        return Err(StratError::UnknownFollowRequest);
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": "Follow request approved!"}),
+    ))
+}
+
+// Turns down someone asking to follow the authenticated user, taking the requester's ID as the path.
+pub async fn reject_follow_request(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    req.context::<Scopes>().unwrap().require("follows:write")?;
+    let user = req.context::<User>().unwrap();
+    if !FollowRequest::delete(req.param("id").unwrap(), user.get_id())? {
+        return Err(StratError::UnknownFollowRequest);
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": "Follow request rejected."}),
+    ))
+}
+
+// Protected users' follows are only shown to themselves and their followers.
+fn require_visible(req: &Request<Body>, target: &User) -> Result<(), StratError> {
+    if target.is_protected() && !Follow::can_view(target, get_viewer(req).as_ref())? {
+        return Err(StratError::ProtectedUser);
+    }
+    Ok(())
+}
+
+fn to_entries(follows: Vec<(User, NaiveDateTime)>) -> Vec<serde_json::Value> {
+    follows
+        .iter()
+        .map(|(user, since)| json!({"user": user.to_summary(), "since": since}))
+        .collect()
+}
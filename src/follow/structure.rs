@@ -0,0 +1,362 @@
+use crate::util::db::{can_connect, get_database};
+use crate::{
+    error::StratError,
+    schema::{
+        follow_counts, follow_counts::dsl as count_dsl, follow_requests,
+        follow_requests::dsl as request_dsl, follows, follows::dsl as follow_dsl, users,
+        users::dsl as user_dsl,
+    },
+    user::structure::User,
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    result::Error as dsl_err, Connection, ExpressionMethods, JoinOnDsl, OptionalExtension,
+    PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+
+// How many users are returned at once when listing follows.
+const FOLLOW_PAGE_SIZE: i64 = 50;
+
+// One user following another.
+// The follower and following counts on users are kept in step with these rows.
+#[derive(Queryable, Insertable, Debug)]
+pub struct Follow {
+    follower: String,
+    followee: String,
+    created: NaiveDateTime,
+}
+
+impl Follow {
+    // Makes follower follow followee, returning whether they weren't already.
+    pub fn create(follower: &str, followee: &str) -> Result<bool, StratError> {
+        if follower == followee {
+            return Err(StratError::FollowSelf);
+        }
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = db.transaction::<_, dsl_err, _>(|| Self::insert(db, follower, followee));
+            match rslt {
+                Ok(created) => Ok(created),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Makes follower stop following followee, returning whether they were.
+    pub fn delete(follower: &str, followee: &str) -> Result<bool, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                let deleted =
+                    diesel::delete(follow_dsl::follows.find((follower, followee))).execute(db)?;
+                if deleted > 0 {
+                    Self::adjust_counts(db, follower, followee, -1)?;
+                }
+                Ok(deleted > 0)
+            });
+            match rslt {
+                Ok(deleted) => Ok(deleted),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Gets a page of the users following someone, with when they followed, newest first.
+    pub fn get_followers(
+        followee: &str,
+        page: i64,
+    ) -> Result<Vec<(User, NaiveDateTime)>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let followers: QueryResult<Vec<(User, NaiveDateTime)>> = follow_dsl::follows
+                .inner_join(users::table.on(user_dsl::id.eq(follow_dsl::follower)))
+                .filter(follow_dsl::followee.eq(followee))
+                .select((users::all_columns, follow_dsl::created))
+                .order(follow_dsl::created.desc())
+                .limit(FOLLOW_PAGE_SIZE)
+                .offset(page.max(0) * FOLLOW_PAGE_SIZE)
+                .load(db);
+            match followers {
+                Ok(f) => Ok(f),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Gets a page of the users someone follows, with when they followed, newest first.
+    pub fn get_following(
+        follower: &str,
+        page: i64,
+    ) -> Result<Vec<(User, NaiveDateTime)>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let following: QueryResult<Vec<(User, NaiveDateTime)>> = follow_dsl::follows
+                .inner_join(users::table.on(user_dsl::id.eq(follow_dsl::followee)))
+                .filter(follow_dsl::follower.eq(follower))
+                .select((users::all_columns, follow_dsl::created))
+                .order(follow_dsl::created.desc())
+                .limit(FOLLOW_PAGE_SIZE)
+                .offset(page.max(0) * FOLLOW_PAGE_SIZE)
+                .load(db);
+            match following {
+                Ok(f) => Ok(f),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Checks whether follower follows followee.
+    pub fn is_following(follower: &str, followee: &str) -> Result<bool, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt: QueryResult<i64> = follow_dsl::follows
+                .find((follower, followee))
+                .count()
+                .get_result(db);
+            match rslt {
+                Ok(n) => Ok(n > 0),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Checks whether someone can see a user's non-public posts,
+    // which is only the user themself and their followers.
+    pub fn can_view(owner: &User, viewer: Option<&User>) -> Result<bool, StratError> {
+        match viewer {
+            Some(v) if v.get_id() == owner.get_id() => Ok(true),
+            Some(v) => Self::is_following(v.get_id(), owner.get_id()),
+            None => Ok(false),
+        }
+    }
+
+    // Inserts a follow and counts it, inside the caller's transaction.
+    fn insert(db: &PgConnection, follower: &str, followee: &str) -> Result<bool, dsl_err> {
+        let follow = Self {
+            follower: follower.to_owned(),
+            followee: followee.to_owned(),
+            created: chrono::Local::now().naive_local(),
+        };
+        let inserted = diesel::insert_into(follows::table)
+            .values(&follow)
+            .on_conflict_do_nothing()
+            .execute(db)?;
+        if inserted > 0 {
+            Self::adjust_counts(db, follower, followee, 1)?;
+        }
+        Ok(inserted > 0)
+    }
+
+    // Moves both users' counts by change, inside the caller's transaction.
+    // Users get their row of counts the first time they follow or are followed.
+    fn adjust_counts(
+        db: &PgConnection,
+        follower: &str,
+        followee: &str,
+        change: i32,
+    ) -> Result<(), dsl_err> {
+        diesel::insert_into(follow_counts::table)
+            .values((
+                count_dsl::user_id.eq(followee),
+                count_dsl::followers.eq(change.max(0)),
+            ))
+            .on_conflict(count_dsl::user_id)
+            .do_update()
+            .set(count_dsl::followers.eq(count_dsl::followers + change))
+            .execute(db)?;
+        diesel::insert_into(follow_counts::table)
+            .values((
+                count_dsl::user_id.eq(follower),
+                count_dsl::following.eq(change.max(0)),
+            ))
+            .on_conflict(count_dsl::user_id)
+            .do_update()
+            .set(count_dsl::following.eq(count_dsl::following + change))
+            .execute(db)?;
+        Ok(())
+    }
+
+    fn match_errors(e: dsl_err) -> StratError {
+        match e.to_string().as_str() {
+            // The other user was deleted while this was happening.
+            "insert or update on table \"follows\" violates foreign key constraint \"follows_followee_fkey\"" => {
+                StratError::UserNotFound
+            }
+            _ => StratError::Unknown,
+        }
+    }
+}
+
+// How many users someone follows and is followed by.
+// These only change through Follow, never by saving a User.
+#[derive(Queryable, Debug, Default, Clone, Copy)]
+pub struct FollowCounts {
+    followers: i32,
+    following: i32,
+}
+
+impl FollowCounts {
+    // Gets a user's counts, which are zero if they've never followed or been followed.
+    pub fn get(user: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let counts: QueryResult<Option<Self>> = count_dsl::follow_counts
+                .find(user)
+                .select((count_dsl::followers, count_dsl::following))
+                .first::<Self>(db)
+                .optional();
+            match counts {
+                Ok(c) => Ok(c.unwrap_or_default()),
+                Err(_e) => Err(StratError::Unknown),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    pub fn get_followers(&self) -> i64 {
+        self.followers.into()
+    }
+
+    pub fn get_following(&self) -> i64 {
+        self.following.into()
+    }
+}
+
+// A request to follow a protected user, waiting on them to approve or reject it.
+#[derive(Queryable, Insertable, Debug)]
+pub struct FollowRequest {
+    requester: String,
+    target: String,
+    created: NaiveDateTime,
+}
+
+impl FollowRequest {
+    // Asks to follow target, returning whether it wasn't already asked.
+    pub fn create(requester: &str, target: &str) -> Result<bool, StratError> {
+        if requester == target {
+            return Err(StratError::FollowSelf);
+        }
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let request = Self {
+                requester: requester.to_owned(),
+                target: target.to_owned(),
+                created: chrono::Local::now().naive_local(),
+            };
+            let rslt = diesel::insert_into(follow_requests::table)
+                .values(&request)
+                .on_conflict_do_nothing()
+                .execute(db);
+            match rslt {
+                Ok(inserted) => Ok(inserted > 0),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Removes a request, returning whether there was one.
+    // This is both how requests are rejected and how requesters take them back.
+    pub fn delete(requester: &str, target: &str) -> Result<bool, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt =
+                diesel::delete(request_dsl::follow_requests.find((requester, target))).execute(db);
+            match rslt {
+                Ok(deleted) => Ok(deleted > 0),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Turns a request into a follow, returning whether there was one.
+    pub fn approve(requester: &str, target: &str) -> Result<bool, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                let deleted =
+                    diesel::delete(request_dsl::follow_requests.find((requester, target)))
+                        .execute(db)?;
+                if deleted > 0 {
+                    Follow::insert(db, requester, target)?;
+                }
+                Ok(deleted > 0)
+            });
+            match rslt {
+                Ok(approved) => Ok(approved),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Approves every request to follow target, used when they stop being protected.
+    pub fn approve_all(target: &str) -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                let requesters: Vec<String> = diesel::delete(
+                    request_dsl::follow_requests.filter(request_dsl::target.eq(target)),
+                )
+                .returning(request_dsl::requester)
+                .get_results(db)?;
+                for requester in requesters.iter() {
+                    Follow::insert(db, requester, target)?;
+                }
+                Ok(requesters.len())
+            });
+            match rslt {
+                Ok(approved) => Ok(approved),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Gets a page of the users asking to follow someone, with when they asked, oldest first.
+    pub fn get_for(target: &str, page: i64) -> Result<Vec<(User, NaiveDateTime)>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let requests: QueryResult<Vec<(User, NaiveDateTime)>> = request_dsl::follow_requests
+                .inner_join(users::table.on(user_dsl::id.eq(request_dsl::requester)))
+                .filter(request_dsl::target.eq(target))
+                .select((users::all_columns, request_dsl::created))
+                .order(request_dsl::created.asc())
+                .limit(FOLLOW_PAGE_SIZE)
+                .offset(page.max(0) * FOLLOW_PAGE_SIZE)
+                .load(db);
+            match requests {
+                Ok(r) => Ok(r),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    fn match_errors(e: dsl_err) -> StratError {
+        match e.to_string().as_str() {
+            // The other user was deleted while this was happening.
+            "insert or update on table \"follow_requests\" violates foreign key constraint \"follow_requests_target_fkey\"" => {
+                StratError::UserNotFound
+            }
+            _ => StratError::Unknown,
+        }
+    }
+}
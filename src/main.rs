@@ -1,12 +1,37 @@
-use auth::routes::{auth_middleware, login, refresh};
+use admin::routes::{
+    admin_middleware, ban_user, change_role, deactivate_user, force_password_reset,
+    get_user_details, reactivate_user, revoke_user_sessions, search_users, suspend_user,
+    unsuspend_user,
+};
+use auth::routes::{
+    auth_middleware, confirm_totp, create_access_token, csrf_middleware, delete_access_token,
+    disable_totp, enroll_totp, list_access_tokens, list_sessions, login, login_totp, logout,
+    logout_all, refresh, revoke_session,
+};
 use error::StratError;
-use hyper::{Body, Request, Response, Server};
-use post::routes::{create_post, delete_post, edit_post};
+use follow::routes::{
+    approve_follow_request, follow_user, list_follow_requests, list_followers, list_following,
+    reject_follow_request, unfollow_user,
+};
+use hyper::{
+    header::{HeaderValue, RETRY_AFTER},
+    Body, Request, Response, Server,
+};
+use oauth::routes::{
+    authorize, authorize_info, create_client, delete_client, introspect, list_clients, revoke,
+    token,
+};
+use post::routes::{create_post, delete_post, edit_post, get_post, list_user_posts};
 use routerify::prelude::*;
 use routerify::{Middleware, Router, RouterService};
 use std::net::SocketAddr;
-use user::routes::create_user;
-use util::json_response;
+use user::routes::{
+    change_email, change_password, create_user, delete_avatar, delete_banner, delete_me,
+    download_export, export_status, forgot_password, get_me, get_profile, get_profile_by_id,
+    request_export, resend_verification, reset_password, update_me, upload_avatar, upload_banner,
+    verify_email,
+};
+use util::{json_response, storage::serve_local};
 //Macro Use
 #[macro_use]
 extern crate diesel;
@@ -15,8 +40,11 @@ extern crate serde;
 #[macro_use]
 extern crate serde_json;
 //modules
+pub mod admin;
 pub mod auth;
 pub mod error;
+pub mod follow;
+pub mod oauth;
 pub mod post;
 pub mod schema;
 pub mod user;
@@ -51,14 +79,63 @@ fn create_router() -> Router<Body, StratError> {
         .middleware(Middleware::pre(logger))
         .post("/user/create", create_user)
         .post("/user/login", login)
+        .post("/user/login/2fa", login_totp)
+        .post("/user/password/forgot", forgot_password)
+        .post("/user/password/reset", reset_password)
+        .get("/user/verify", verify_email)
+        // Registered after the fixed user routes so they aren't taken as nicknames.
+        .get("/user/id/:id", get_profile_by_id)
+        .get("/user/id/:id/posts", list_user_posts)
+        .get("/user/id/:id/followers", list_followers)
+        .get("/user/id/:id/following", list_following)
+        .get("/user/:nickname", get_profile)
         .post("/auth/refresh", refresh)
+        .post("/oauth/token", token)
+        .post("/oauth/revoke", revoke)
+        .post("/oauth/introspect", introspect)
+        .get("/post/:id", get_post)
+        .get("/media/:name", serve_local)
         .get("/", index_handler)
         .scope(
             // set a prefix for all the authorization routes
             "/v1/",
             Router::builder()
                 .middleware(Middleware::pre(auth_middleware))
+                .middleware(Middleware::pre(csrf_middleware))
                 .get("/", index_handler)
+                .post("/auth/logout", logout)
+                .post("/auth/logout-all", logout_all)
+                .post("/user/verify/resend", resend_verification)
+                .get("/me", get_me)
+                .patch("/me", update_me)
+                .delete("/me", delete_me)
+                .post("/me/avatar", upload_avatar)
+                .delete("/me/avatar", delete_avatar)
+                .post("/me/banner", upload_banner)
+                .delete("/me/banner", delete_banner)
+                .post("/me/export", request_export)
+                .get("/me/export/:id", export_status)
+                .get("/me/export/:id/download", download_export)
+                .post("/me/password", change_password)
+                .post("/me/email", change_email)
+                .post("/auth/2fa/enroll", enroll_totp)
+                .post("/auth/2fa/confirm", confirm_totp)
+                .post("/auth/2fa/disable", disable_totp)
+                .get("/auth/sessions", list_sessions)
+                .delete("/auth/sessions/:id", revoke_session)
+                .post("/auth/tokens", create_access_token)
+                .get("/auth/tokens", list_access_tokens)
+                .delete("/auth/tokens/:id", delete_access_token)
+                .get("/oauth/authorize", authorize_info)
+                .post("/oauth/authorize", authorize)
+                .post("/oauth/clients", create_client)
+                .get("/oauth/clients", list_clients)
+                .delete("/oauth/clients/:id", delete_client)
+                .post("/users/:id/follow", follow_user)
+                .delete("/users/:id/follow", unfollow_user)
+                .get("/me/follow-requests", list_follow_requests)
+                .post("/me/follow-requests/:id/approve", approve_follow_request)
+                .post("/me/follow-requests/:id/reject", reject_follow_request)
                 .post("/post/create", create_post)
                 .patch("/post/edit", edit_post)
                 .delete("/post/delete", delete_post)
@@ -66,6 +143,27 @@ fn create_router() -> Router<Body, StratError> {
                 .build()
                 .unwrap(),
         )
+        .scope(
+            // Staff only routes, each also checks its own permission
+            "/admin/",
+            Router::builder()
+                .middleware(Middleware::pre(auth_middleware))
+                .middleware(Middleware::pre(csrf_middleware))
+                .middleware(Middleware::pre(admin_middleware))
+                .get("/users", search_users)
+                .get("/users/:id", get_user_details)
+                .post("/users/:id/role", change_role)
+                .post("/users/:id/password-reset", force_password_reset)
+                .post("/users/:id/revoke-sessions", revoke_user_sessions)
+                .post("/users/:id/suspend", suspend_user)
+                .post("/users/:id/unsuspend", unsuspend_user)
+                .post("/users/:id/ban", ban_user)
+                .post("/users/:id/deactivate", deactivate_user)
+                .post("/users/:id/reactivate", reactivate_user)
+                .err_handler(error_handler)
+                .build()
+                .unwrap(),
+        )
         .err_handler(error_handler)
         .build()
         .unwrap()
@@ -73,7 +171,15 @@ fn create_router() -> Router<Body, StratError> {
 
 // Take an Error, if its our type, we return this status and response
 pub fn err_to_resp(e: Box<dyn std::error::Error + Sync + Send + 'static>) -> Response<Body> {
-    if let Some(e) = e.downcast_ref::<StratError>() {
+    if let Some(StratError::LoginLocked(secs)) = e.downcast_ref::<StratError>() {
+        // Let clients know when they can try again.
+        let mut resp = json_response(
+            json!({"status": 500, "response": format!("{}", e), "retry_after": secs}),
+        );
+        resp.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(*secs));
+        resp
+    } else if let Some(e) = e.downcast_ref::<StratError>() {
         json_response(json!({"status": 500, "response": format!("{}", e)}))
     } else {
         json_response(json!({"status": 500, "response": "An internal server error has occured!"}))
@@ -96,6 +202,15 @@ async fn error_handler(e: routerify::Error) -> Response<Body> {
 // Creating our Router and Running it.
 #[tokio::main]
 async fn main() {
+    // Loaded up front so a missing blocklist or unknown storage stops the server instead of a request.
+    lazy_static::initialize(&user::password::BLOCKLIST);
+    lazy_static::initialize(&util::storage::STORAGE);
+    if util::config::CONFIG.session_gc_interval > 0 {
+        tokio::spawn(auth::gc::collect_sessions());
+    } else if util::config::CONFIG.deletion_grace_days > 0 {
+        // Scheduled deletions are only carried out by the GC, so they'd never happen.
+        panic!("DELETION_GRACE_DAYS needs SESSION_GC_INTERVAL_SECS to be more than 0");
+    }
     let router = create_router();
     let service = RouterService::new(router).unwrap();
     let addr = SocketAddr::from(([127, 0, 0, 1], 3001));
@@ -0,0 +1,381 @@
+use super::structure::{OAuthClient, OAuthCode};
+use crate::{
+    auth::{
+        permissions::{authorize_owned, Permission},
+        routes::require_session,
+        structure::{AccessToken, SCOPES},
+    },
+    error::StratError,
+    user::structure::User,
+    util::{json_response, parse_basic, parse_body, parse_form},
+};
+use hyper::{
+    header::{HeaderValue, WWW_AUTHENTICATE},
+    Body, Request, Response, StatusCode,
+};
+use routerify::ext::RequestExt;
+use std::collections::HashMap;
+
+// The parameters of an authorization request, as described by RFC 6749 and RFC 7636.
+#[derive(Deserialize)]
+struct AuthorizeParams {
+    client_id: String,
+    redirect_uri: String,
+    response_type: String,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: String,
+    code_challenge_method: String,
+    // Only used when the user answers the consent screen.
+    #[serde(default)]
+    approve: bool,
+}
+
+// Registers a new OAuth client owned by the authenticated user.
+// Public clients (like mobile apps) can't keep a secret, so only confidential ones get one.
+// Takes the client's details as the body
+// ex: {"name": "My App", "redirect_uris": ["https://example.com/callback"], "confidential": true}
+pub async fn create_client(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct ClientCreate {
+        name: String,
+        redirect_uris: Vec<String>,
+        confidential: bool,
+    }
+
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    let c: ClientCreate = match parse_body::<ClientCreate>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if c.name.is_empty() || c.name.chars().count() > 64 {
+        return Err(StratError::OversizedField("name".to_owned(), 64));
+    }
+    // Fragments aren't allowed in redirect URIs by RFC 6749.
+    if c.redirect_uris.is_empty()
+        || c.redirect_uris
+            .iter()
+            .any(|uri| !uri.contains("://") || uri.contains('#'))
+    {
+        return Err(StratError::BadRedirect);
+    }
+    let (client, secret) = OAuthClient::new(
+        user.get_id().to_owned(),
+        c.name,
+        c.redirect_uris,
+        c.confidential,
+    );
+    if let Some(e) = client.save_client() {
+        return Err(e);
+    }
+    Ok(json_response(json!({
+        "status": 200,
+        "response": "Client registered! The secret won't be shown again, so store it somewhere safe.",
+        "client_id": client.get_id(),
+        "client_secret": secret
+    })))
+}
+
+// Lists the OAuth clients registered by the authenticated user.
+pub async fn list_clients(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    match OAuthClient::get_by_owner(user.get_id()) {
+        Ok(clients) => Ok(json_response(json!({"status": 200, "response": clients}))),
+        Err(e) => Err(e),
+    }
+}
+
+// Deletes one of the authenticated user's OAuth clients, revoking every token issued to it.
+pub async fn delete_client(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    let client = match OAuthClient::get_by_id(req.param("id").unwrap()) {
+        Ok(c) => c,
+        Err(e) => return Err(e),
+    };
+    // Someone else's client is reported as missing so IDs can't be probed,
+    // unless they're allowed to delete it anyway.
+    if authorize_owned(&user, client.get_owner(), Permission::DeleteAnyClient).is_err() {
+        return Err(StratError::UnknownClient);
+    }
+    match client.delete_client() {
+        None => Ok(json_response(
+            json!({"status": 200, "response": "Client deleted!"}),
+        )),
+        Some(e) => Err(e),
+    }
+}
+
+// Validates an authorization request and returns what should be shown on the consent screen.
+// Takes the standard parameters in the query string
+// ex: /v1/oauth/authorize?response_type=code&client_id=...&redirect_uri=...&scope=posts:read&state=...&code_challenge=...&code_challenge_method=S256
+pub async fn authorize_info(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let query: HashMap<String, String> = match req.uri().query() {
+        Some(q) => form_urlencoded::parse(q.as_bytes()).into_owned().collect(),
+        None => HashMap::new(),
+    };
+    let params: AuthorizeParams =
+        match serde_json::to_value(query).and_then(serde_json::from_value::<AuthorizeParams>) {
+            Ok(p) => p,
+            Err(e) => {
+                return Ok(json_response(
+                    json!({"status": 500, "response": format!("Failed to parse query: {}", e)}),
+                ))
+            }
+        };
+    let (client, scopes) = validate_authorize(&params)?;
+    Ok(json_response(json!({
+        "status": 200,
+        "response": {
+            "client": {"id": client.get_id(), "name": client.get_name()},
+            "scopes": scopes,
+            "redirect_uri": params.redirect_uri
+        }
+    })))
+}
+
+// Answers an authorization request on behalf of the authenticated user.
+// Returns the URI the user should be redirected to, which carries the code or the denial.
+// Takes the same parameters as authorize_info plus the user's answer as the body
+// ex: {"client_id": "...", "redirect_uri": "...", "response_type": "code", "scope": "posts:read", "state": "...", "code_challenge": "...", "code_challenge_method": "S256", "approve": true}
+pub async fn authorize(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    let params: AuthorizeParams = match parse_body::<AuthorizeParams>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    let (client, scopes) = validate_authorize(&params)?;
+    let mut answer: Vec<(&str, String)> = Vec::new();
+    if params.approve {
+        let (code, raw) = OAuthCode::new(
+            client.get_id().to_owned(),
+            user.get_id().to_owned(),
+            params.redirect_uri.clone(),
+            scopes,
+            params.code_challenge.clone(),
+        );
+        if let Some(e) = code.save_code() {
+            return Err(e);
+        }
+        answer.push(("code", raw));
+    } else {
+        answer.push(("error", "access_denied".to_owned()));
+    }
+    if let Some(state) = &params.state {
+        answer.push(("state", state.to_owned()));
+    }
+    Ok(json_response(json!({
+        "status": 200,
+        "response": "Authorization answered!",
+        "redirect": redirect_with(&params.redirect_uri, &answer)
+    })))
+}
+
+// Exchanges an authorization code or refresh token for an access token.
+// Takes a form urlencoded body as described by RFC 6749, the client can authenticate
+// using HTTP Basic or the client_id and client_secret fields.
+// Errors are sent the way RFC 6749 describes, so OAuth libraries can understand them.
+pub async fn token(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    Ok(issue_token(req).await.unwrap_or_else(oauth_error))
+}
+
+async fn issue_token(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let basic = parse_basic(req.headers());
+    let form = match parse_form(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(error_body(StatusCode::BAD_REQUEST, "invalid_request", &e)),
+    };
+    let client = authenticate_client(basic, &form)?;
+    let field = |name: &str| form.get(name).map(|v| v.as_str()).unwrap_or("");
+    let (access, token, refresh) = match field("grant_type") {
+        "authorization_code" => {
+            let code = OAuthCode::consume(client.get_id(), field("code"))?;
+            if code.get_redirect_uri() != field("redirect_uri") {
+                return Err(StratError::BadRedirect);
+            }
+            if !code.verify_pkce(field("code_verifier")) {
+                return Err(StratError::InvalidPkce);
+            }
+            User::get_user(code.get_owner())?.check_active()?;
+            let (access, token, refresh) = AccessToken::new_oauth(
+                code.get_owner().to_owned(),
+                client.get_id().to_owned(),
+                client.get_name().to_owned(),
+                code.get_scopes().to_vec(),
+            );
+            if let Some(e) = access.save_token() {
+                return Err(e);
+            }
+            (access, token, refresh)
+        }
+        "refresh_token" => {
+            let (access, token, refresh) =
+                AccessToken::refresh_oauth(client.get_id(), field("refresh_token"))?;
+            User::get_user(access.get_owner())?.check_active()?;
+            (access, token, refresh)
+        }
+        _ => return Err(StratError::UnsupportedGrant),
+    };
+    let expires_in = access
+        .get_expiry()
+        .map(|e| (e - chrono::Local::now().naive_local()).num_seconds());
+    Ok(json_response(json!({
+        "access_token": token,
+        "token_type": "Bearer",
+        "expires_in": expires_in,
+        "refresh_token": refresh,
+        "scope": access.get_scopes().join(" ")
+    })))
+}
+
+// Revokes an access token or refresh issued to the authenticated client, as described by RFC 7009.
+// Unknown tokens are ignored, so this always succeeds for a valid client.
+pub async fn revoke(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    Ok(revoke_token(req).await.unwrap_or_else(oauth_error))
+}
+
+async fn revoke_token(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let basic = parse_basic(req.headers());
+    let form = match parse_form(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(error_body(StatusCode::BAD_REQUEST, "invalid_request", &e)),
+    };
+    let client = authenticate_client(basic, &form)?;
+    let token = form.get("token").map(|t| t.as_str()).unwrap_or("");
+    match AccessToken::get_for_client(client.get_id(), token) {
+        Ok(access) => {
+            if let Some(e) = access.delete_token() {
+                return Err(e);
+            }
+        }
+        Err(StratError::UnknownAccessToken) => {}
+        Err(e) => return Err(e),
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": "Token revoked!"}),
+    ))
+}
+
+// Describes an access token or refresh issued to the authenticated client, as described by RFC 7662.
+pub async fn introspect(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    Ok(introspect_token(req).await.unwrap_or_else(oauth_error))
+}
+
+async fn introspect_token(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let basic = parse_basic(req.headers());
+    let form = match parse_form(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(error_body(StatusCode::BAD_REQUEST, "invalid_request", &e)),
+    };
+    let client = authenticate_client(basic, &form)?;
+    let token = form.get("token").map(|t| t.as_str()).unwrap_or("");
+    let access = match AccessToken::get_for_client(client.get_id(), token) {
+        Ok(a) => a,
+        Err(StratError::UnknownAccessToken) => return Ok(json_response(json!({"active": false}))),
+        Err(e) => return Err(e),
+    };
+    Ok(json_response(json!({
+        "active": access.is_active(),
+        "scope": access.get_scopes().join(" "),
+        "client_id": client.get_id(),
+        "sub": access.get_owner(),
+        "token_type": "Bearer",
+        "iat": access.get_created().timestamp(),
+        "exp": access.get_expiry().map(|e| e.timestamp())
+    })))
+}
+
+// Checks everything about an authorization request that doesn't depend on the user's answer.
+// Returns the client and the scopes it asked for.
+fn validate_authorize(params: &AuthorizeParams) -> Result<(OAuthClient, Vec<String>), StratError> {
+    let client = OAuthClient::get_by_id(&params.client_id)?;
+    // Until the redirect is known to be safe, errors can't be sent back to the client.
+    if !client.allows_redirect(&params.redirect_uri) {
+        return Err(StratError::BadRedirect);
+    }
+    if params.response_type != "code" {
+        return Err(StratError::UnsupportedGrant);
+    }
+    // PKCE is required for every client, and only with S256.
+    let challenge_len = params.code_challenge.len();
+    if params.code_challenge_method != "S256" || !(43..=128).contains(&challenge_len) {
+        return Err(StratError::InvalidPkce);
+    }
+    let scopes: Vec<String> = params
+        .scope
+        .as_deref()
+        .unwrap_or("")
+        .split_whitespace()
+        .map(|s| s.to_owned())
+        .collect();
+    if let Some(s) = scopes.iter().find(|s| !SCOPES.contains(&s.as_str())) {
+        return Err(StratError::UnknownScope(s.to_owned()));
+    }
+    Ok((client, scopes))
+}
+
+// Authenticates a client using HTTP Basic, or the client_id and client_secret fields.
+fn authenticate_client(
+    basic: Option<(String, String)>,
+    form: &HashMap<String, String>,
+) -> Result<OAuthClient, StratError> {
+    match basic {
+        Some((id, secret)) => OAuthClient::authenticate(&id, Some(&secret)),
+        None => match form.get("client_id") {
+            Some(id) => {
+                OAuthClient::authenticate(id, form.get("client_secret").map(|s| s.as_str()))
+            }
+            None => Err(StratError::InvalidClient),
+        },
+    }
+}
+
+// Turns an error from the token, revoke or introspect endpoints into an
+// error response as described by RFC 6749 section 5.2.
+fn oauth_error(e: StratError) -> Response<Body> {
+    let (status, error) = match e {
+        StratError::InvalidClient | StratError::UnknownClient => {
+            (StatusCode::UNAUTHORIZED, "invalid_client")
+        }
+        StratError::UnsupportedGrant => (StatusCode::BAD_REQUEST, "unsupported_grant_type"),
+        StratError::UnknownScope(_) => (StatusCode::BAD_REQUEST, "invalid_scope"),
+        // The code or refresh is no good, including when its user can't use it anymore.
+        StratError::InvalidGrant
+        | StratError::RefreshReused
+        | StratError::BadRedirect
+        | StratError::InvalidPkce
+        | StratError::UserNotFound
+        | StratError::AccountSuspended(_, _)
+        | StratError::AccountBanned(_)
+        | StratError::AccountDeactivated => (StatusCode::BAD_REQUEST, "invalid_grant"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "server_error"),
+    };
+    let mut response = error_body(status, error, &format!("{}", e));
+    // Clients that failed to authenticate are told how they can.
+    if status == StatusCode::UNAUTHORIZED {
+        response.headers_mut().insert(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=\"oauth\""),
+        );
+    }
+    response
+}
+
+fn error_body(status: StatusCode, error: &str, description: &str) -> Response<Body> {
+    let mut response = json_response(json!({"error": error, "error_description": description}));
+    *response.status_mut() = status;
+    response
+}
+
+// Adds parameters to the query string of a redirect URI.
+fn redirect_with(uri: &str, params: &[(&str, String)]) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params.iter().map(|(k, v)| (*k, v.as_str())))
+        .finish();
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", uri, separator, query)
+}
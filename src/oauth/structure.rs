@@ -0,0 +1,236 @@
+use crate::util::{
+    constant_eq,
+    db::{can_connect, get_database},
+    gen_random, hash_token,
+};
+use crate::{
+    error::StratError,
+    schema::{
+        oauth_clients, oauth_clients::dsl as client_dsl, oauth_codes, oauth_codes::dsl as code_dsl,
+    },
+};
+use chrono::{Duration, NaiveDateTime};
+use diesel::{
+    result::Error as dsl_err, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use sha2::{Digest, Sha256};
+
+// A third-party application that can act on behalf of users.
+#[derive(Queryable, Insertable, Serialize, Debug, Clone)]
+#[table_name = "oauth_clients"]
+pub struct OAuthClient {
+    id: String,
+    owner: String,
+    name: String,
+    #[serde(skip)]
+    secret: Option<String>,
+    redirect_uris: Vec<String>,
+    created: NaiveDateTime,
+}
+
+impl OAuthClient {
+    // Creates a new client but doesn't save it.
+    // Confidential clients get a secret, which is returned raw to be shown once.
+    pub fn new(
+        owner: String,
+        name: String,
+        redirect_uris: Vec<String>,
+        confidential: bool,
+    ) -> (Self, Option<String>) {
+        let secret = if confidential {
+            Some(gen_random(48))
+        } else {
+            None
+        };
+        let client = Self {
+            id: gen_random(24),
+            owner,
+            name,
+            secret: secret.as_ref().map(|s| hash_token(s)),
+            redirect_uris,
+            created: chrono::Local::now().naive_local(),
+        };
+        (client, secret)
+    }
+
+    pub fn get_by_id(id: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let client: QueryResult<Self> = client_dsl::oauth_clients.find(id).first::<Self>(db);
+            match client {
+                Ok(c) => Ok(c),
+                Err(_e) => Err(StratError::UnknownClient),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Gets every client registered by a user.
+    pub fn get_by_owner(owner: &str) -> Result<Vec<Self>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let clients: QueryResult<Vec<Self>> = client_dsl::oauth_clients
+                .filter(client_dsl::owner.eq(owner))
+                .order(client_dsl::created.desc())
+                .load::<Self>(db);
+            match clients {
+                Ok(c) => Ok(c),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Finds a client and checks its credentials.
+    // Public clients have no secret, they're protected by PKCE instead.
+    pub fn authenticate(id: &str, secret: Option<&str>) -> Result<Self, StratError> {
+        let client = match Self::get_by_id(id) {
+            Ok(c) => c,
+            Err(StratError::UnknownClient) => return Err(StratError::InvalidClient),
+            Err(e) => return Err(e),
+        };
+        match (&client.secret, secret) {
+            (None, _) => Ok(client),
+            (Some(hash), Some(secret)) if constant_eq(hash, &hash_token(secret)) => Ok(client),
+            _ => Err(StratError::InvalidClient),
+        }
+    }
+
+    pub fn save_client(&self) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            match diesel::insert_into(oauth_clients::table)
+                .values(self)
+                .execute(db)
+            {
+                Ok(_) => return None,
+                Err(e) => return Some(Self::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Deletes this client, its codes and tokens go with it.
+    pub fn delete_client(self) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            match diesel::delete(client_dsl::oauth_clients.find(&self.id)).execute(db) {
+                Ok(_) => return None,
+                Err(e) => return Some(Self::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Redirect URIs must match one that was registered exactly.
+    pub fn allows_redirect(&self, uri: &str) -> bool {
+        self.redirect_uris.iter().any(|r| r == uri)
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    // Converts Diesel Errors into regular Errors.
+    fn match_errors(_e: dsl_err) -> StratError {
+        StratError::Unknown
+    }
+}
+
+// A short-lived, single-use code a client exchanges for a token.
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "oauth_codes"]
+pub struct OAuthCode {
+    code: String,
+    client_id: String,
+    owner: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    code_challenge: String,
+    expiry: NaiveDateTime,
+}
+
+impl OAuthCode {
+    // Creates a new code but doesn't save it, returning the raw code to send to the client.
+    pub fn new(
+        client_id: String,
+        owner: String,
+        redirect_uri: String,
+        scopes: Vec<String>,
+        code_challenge: String,
+    ) -> (Self, String) {
+        let code = gen_random(40);
+        let oauth_code = Self {
+            code: hash_token(&code),
+            client_id,
+            owner,
+            redirect_uri,
+            scopes,
+            code_challenge,
+            expiry: chrono::Local::now().naive_local() + Duration::minutes(10),
+        };
+        (oauth_code, code)
+    }
+
+    pub fn save_code(&self) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            match diesel::insert_into(oauth_codes::table)
+                .values(self)
+                .execute(db)
+            {
+                Ok(_) => return None,
+                Err(e) => return Some(OAuthClient::match_errors(e)),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Uses up a code issued to a client.
+    pub fn consume(client_id: &str, code: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let oauth_code: QueryResult<Self> = diesel::delete(
+                code_dsl::oauth_codes
+                    .filter(code_dsl::code.eq(hash_token(code)))
+                    .filter(code_dsl::client_id.eq(client_id)),
+            )
+            .get_result::<Self>(db);
+            match oauth_code {
+                Ok(c) if c.expiry >= chrono::Local::now().naive_local() => Ok(c),
+                _ => Err(StratError::InvalidGrant),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Checks a PKCE code verifier against the S256 challenge the code was created with.
+    pub fn verify_pkce(&self, verifier: &str) -> bool {
+        let challenge =
+            base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+        constant_eq(&challenge, &self.code_challenge)
+    }
+
+    pub fn get_owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn get_redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    pub fn get_scopes(&self) -> &[String] {
+        &self.scopes
+    }
+}
@@ -1,8 +1,14 @@
 use crate::{
+    auth::{
+        permissions::{authorize_owned, Permission},
+        routes::get_viewer,
+        structure::Scopes,
+    },
     error::StratError,
+    follow::structure::Follow,
     post::structure::Post,
     user::structure,
-    util::{json_response, parse_body},
+    util::{config::CONFIG, get_page, json_response, parse_body},
 };
 use hyper::{Body, Request, Response};
 use multer::{Constraints, Multipart, SizeLimit};
@@ -11,6 +17,10 @@ use routerify::ext::RequestExt;
 // Creates a post using Multipart Form Data
 pub async fn create_post(req: Request<Body>) -> Result<Response<Body>, StratError> {
     let user = req.context::<structure::User>().unwrap();
+    req.context::<Scopes>().unwrap().require("posts:write")?;
+    if CONFIG.require_verified_to_post && !user.is_verified() {
+        return Err(StratError::EmailUnverified);
+    }
     let boundary = req
         .headers()
         .get("Content-Type")
@@ -18,8 +28,8 @@ pub async fn create_post(req: Request<Body>) -> Result<Response<Body>, StratErro
         .and_then(|ct| multer::parse_boundary(ct).ok());
 
     let constraints = Constraints::new()
-        // Only allow Content or Media
-        .allowed_fields(vec!["content", "media"])
+        // Only allow Content, Media or whether it's Public
+        .allowed_fields(vec!["content", "media", "public"])
         .size_limit(
             SizeLimit::new()
                 // Set 15mb as size limit for the whole stream body.
@@ -27,7 +37,8 @@ pub async fn create_post(req: Request<Body>) -> Result<Response<Body>, StratErro
                 // Set 8mb as size limit for all fields.
                 .per_field(8 * 1024 * 1024)
                 // The post's content can only contain 500 characters.
-                .for_field("content", 500),
+                .for_field("content", 500)
+                .for_field("public", 5),
         );
     if boundary.is_none() {
         return Err(StratError::BadMulti);
@@ -59,6 +70,7 @@ async fn parse_post(
 ) -> Result<Post, StratError> {
     let mut multipart = Multipart::new_with_constraints(body, boundary, constraints);
     let mut content = String::new();
+    let mut public = true;
     // Hacky way to return my own Error Type while parsing through all fields.
     // Probably better way to do this, will improve soon(tm)
     while match multipart.next_field().await {
@@ -76,6 +88,15 @@ async fn parse_post(
                         Err(_e) => return Err(StratError::BadMulti),
                     };
                 }
+                // Only followers can see posts that aren't public.
+                // Anything but "true" or "false" is rejected, so a typo can't make a post public.
+                "public" => {
+                    public = match field.text().await {
+                        Ok(t) if t.trim().eq_ignore_ascii_case("true") => true,
+                        Ok(t) if t.trim().eq_ignore_ascii_case("false") => false,
+                        _ => return Err(StratError::BadMulti),
+                    };
+                }
                 _ => {
                     // This should be impossible
                     return Err(StratError::BadMulti)
@@ -101,11 +122,50 @@ async fn parse_post(
     if content.is_empty() {
         return Err(StratError::NeedsContent)
     }
-    Ok(Post::new(content, owner, true))
+    Ok(Post::new(content, owner, public))
+}
+
+// Gets a post using its ID.
+// Posts that aren't public, or are made by protected users, can only be seen by the owner and their followers.
+pub async fn get_post(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let post = match Post::get_by_id(req.param("id").unwrap()) {
+        Ok(p) => p,
+        Err(e) => return Err(e),
+    };
+    // Posts that can't be seen, and posts of accounts that aren't active, are hidden as if they don't exist.
+    let owner = structure::User::get_user(post.get_owner())?;
+    if owner.are_posts_hidden() {
+        return Err(StratError::UnknownPost);
+    }
+    if (!post.is_public() || owner.is_protected())
+        && !Follow::can_view(&owner, get_viewer(&req).as_ref())?
+    {
+        return Err(StratError::UnknownPost);
+    }
+    Ok(json_response(json!({"status": 200, "response": post})))
+}
+
+// Lists the posts of a user, 20 at a time.
+// Only the owner and their followers see posts that aren't public, or any posts of protected users.
+// Takes the page as a query parameter ex: /user/id/ABCDEFG.../posts?page=0
+pub async fn list_user_posts(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let owner = structure::User::get_user(req.param("id").unwrap())?;
+    let page = get_page(&req);
+    let posts = if owner.are_posts_hidden() {
+        Vec::new()
+    } else {
+        let can_view = Follow::can_view(&owner, get_viewer(&req).as_ref())?;
+        if owner.is_protected() && !can_view {
+            return Err(StratError::ProtectedUser);
+        }
+        Post::get_page_by_owner(owner.get_id(), can_view, page)?
+    };
+    Ok(json_response(json!({"status": 200, "response": posts})))
 }
 
 pub async fn edit_post(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
     let user = req.context::<structure::User>().unwrap();
+    req.context::<Scopes>().unwrap().require("posts:write")?;
     #[derive(Serialize, Deserialize)]
     struct PostEdit {
         id: String,
@@ -130,11 +190,8 @@ pub async fn edit_post(mut req: Request<Body>) -> Result<Response<Body>, StratEr
     };
 
     // Permission Check
-    // TODO: Replace with proper permissions system!
-    if post.get_owner() != user.get_id() {
-        return Err(StratError::NoPermission);
-    }
-    
+    authorize_owned(&user, post.get_owner(), Permission::EditAnyPost)?;
+
     // Edit
     post.edit(p.content);
 
@@ -150,6 +207,7 @@ pub async fn edit_post(mut req: Request<Body>) -> Result<Response<Body>, StratEr
 
 pub async fn delete_post(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
     let user = req.context::<structure::User>().unwrap();
+    req.context::<Scopes>().unwrap().require("posts:write")?;
     #[derive(Serialize, Deserialize)]
     struct PostDelete {
         id: String,
@@ -167,10 +225,7 @@ pub async fn delete_post(mut req: Request<Body>) -> Result<Response<Body>, Strat
     };
 
     // Permission Check
-    // TODO: Replace with proper permissions system!
-    if post.get_owner() != user.get_id() {
-        return Err(StratError::NoPermission);
-    }
+    authorize_owned(&user, post.get_owner(), Permission::DeleteAnyPost)?;
 
     // Delete
     if let Some(e) = post.delete_post() {
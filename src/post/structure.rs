@@ -7,6 +7,9 @@ use chrono::NaiveDateTime;
 use diesel::{
     result::Error as dsl_err, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
 };
+// How many posts are returned at once when listing them.
+const POST_PAGE_SIZE: i64 = 20;
+
 #[derive(Queryable, Insertable, Serialize, Deserialize, Debug, AsChangeset)]
 pub struct Post {
     id: String,
@@ -56,6 +59,74 @@ impl Post {
         }
     }
 
+    // Gets every post made by a user, oldest first.
+    pub fn get_by_owner(owner: &str) -> Result<Vec<Self>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let posts: QueryResult<Vec<Self>> = post_dsl::posts
+                .filter(posts::owner.eq(owner))
+                .order(posts::created.asc())
+                .load::<Self>(db);
+            match posts {
+                Ok(p) => Ok(p),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Gets a page of the posts made by a user, newest first, private posts are only included if asked for.
+    pub fn get_page_by_owner(
+        owner: &str,
+        include_private: bool,
+        page: i64,
+    ) -> Result<Vec<Self>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let mut query = post_dsl::posts.filter(posts::owner.eq(owner)).into_boxed();
+            if !include_private {
+                query = query.filter(posts::public.eq(true));
+            }
+            let posts: QueryResult<Vec<Self>> = query
+                .order(posts::created.desc())
+                .limit(POST_PAGE_SIZE)
+                .offset(page.max(0) * POST_PAGE_SIZE)
+                .load::<Self>(db);
+            match posts {
+                Ok(p) => Ok(p),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Counts the posts made by a user, private posts are only counted if asked for.
+    pub fn count_by_owner(owner: &str, include_private: bool) -> Result<i64, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt: QueryResult<i64> = if include_private {
+                post_dsl::posts
+                    .filter(posts::owner.eq(owner))
+                    .count()
+                    .get_result(db)
+            } else {
+                post_dsl::posts
+                    .filter(posts::owner.eq(owner))
+                    .filter(posts::public.eq(true))
+                    .count()
+                    .get_result(db)
+            };
+            match rslt {
+                Ok(n) => Ok(n),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
     // Saves the post instance back into the datbase
     pub fn save_post(&self) -> Option<StratError> {
         let db: &PgConnection = &get_database();
@@ -105,4 +176,8 @@ impl Post {
     pub fn get_id(&self) -> &str {
         &self.id
     }
+
+    pub fn is_public(&self) -> bool {
+        self.public
+    }
 }
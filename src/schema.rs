@@ -1,3 +1,18 @@
+table! {
+    access_tokens (id) {
+        id -> Varchar,
+        owner -> Varchar,
+        name -> Varchar,
+        token -> Varchar,
+        scopes -> Array<Text>,
+        created -> Timestamp,
+        last_used -> Nullable<Timestamp>,
+        expiry -> Nullable<Timestamp>,
+        client_id -> Nullable<Varchar>,
+        refresh -> Nullable<Varchar>,
+    }
+}
+
 table! {
     auths (refresh) {
         token -> Varchar,
@@ -5,6 +20,106 @@ table! {
         owner -> Varchar,
         expiry -> Timestamp,
         created -> Timestamp,
+        id -> Varchar,
+        user_agent -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        last_used -> Timestamp,
+    }
+}
+
+table! {
+    data_exports (id) {
+        id -> Varchar,
+        owner -> Varchar,
+        status -> Varchar,
+        created -> Timestamp,
+        completed -> Nullable<Timestamp>,
+        expiry -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    email_verifications (token) {
+        token -> Varchar,
+        owner -> Varchar,
+        email -> Varchar,
+        expiry -> Timestamp,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    follow_counts (user_id) {
+        user_id -> Varchar,
+        followers -> Int4,
+        following -> Int4,
+    }
+}
+
+table! {
+    follow_requests (requester, target) {
+        requester -> Varchar,
+        target -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    follows (follower, followee) {
+        follower -> Varchar,
+        followee -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    login_challenges (token) {
+        token -> Varchar,
+        owner -> Varchar,
+        expiry -> Timestamp,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    login_throttles (scope, key) {
+        scope -> Varchar,
+        key -> Varchar,
+        failures -> Int4,
+        locked_until -> Nullable<Timestamp>,
+        last_failure -> Timestamp,
+    }
+}
+
+table! {
+    oauth_clients (id) {
+        id -> Varchar,
+        owner -> Varchar,
+        name -> Varchar,
+        secret -> Nullable<Varchar>,
+        redirect_uris -> Array<Text>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    oauth_codes (code) {
+        code -> Varchar,
+        client_id -> Varchar,
+        owner -> Varchar,
+        redirect_uri -> Text,
+        scopes -> Array<Text>,
+        code_challenge -> Varchar,
+        expiry -> Timestamp,
+    }
+}
+
+table! {
+    password_resets (token) {
+        token -> Varchar,
+        owner -> Varchar,
+        expiry -> Timestamp,
+        created -> Timestamp,
     }
 }
 
@@ -19,6 +134,29 @@ table! {
     }
 }
 
+table! {
+    recovery_codes (owner, code) {
+        owner -> Varchar,
+        code -> Varchar,
+    }
+}
+
+table! {
+    used_oauth_refreshes (refresh) {
+        refresh -> Varchar,
+        token_id -> Varchar,
+        rotated -> Timestamp,
+    }
+}
+
+table! {
+    used_refreshes (refresh) {
+        refresh -> Varchar,
+        family -> Varchar,
+        rotated -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Varchar,
@@ -29,10 +167,55 @@ table! {
         is_priv -> Bool,
         updated_at -> Timestamp,
         created_at -> Timestamp,
+        verified -> Bool,
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled -> Bool,
+        totp_last_step -> Int8,
+        display_name -> Nullable<Varchar>,
+        bio -> Nullable<Varchar>,
+        location -> Nullable<Varchar>,
+        website -> Nullable<Varchar>,
+        nickname_changed -> Nullable<Timestamp>,
+        deletion_scheduled -> Nullable<Timestamp>,
+        avatar -> Nullable<Varchar>,
+        banner -> Nullable<Varchar>,
+        status -> Varchar,
+        status_until -> Nullable<Timestamp>,
+        status_reason -> Nullable<Varchar>,
+        protected -> Bool,
     }
 }
 
+joinable!(access_tokens -> oauth_clients (client_id));
+joinable!(access_tokens -> users (owner));
 joinable!(auths -> users (owner));
+joinable!(data_exports -> users (owner));
+joinable!(email_verifications -> users (owner));
+joinable!(follow_counts -> users (user_id));
+joinable!(login_challenges -> users (owner));
+joinable!(oauth_clients -> users (owner));
+joinable!(oauth_codes -> oauth_clients (client_id));
+joinable!(oauth_codes -> users (owner));
+joinable!(password_resets -> users (owner));
 joinable!(posts -> users (owner));
+joinable!(recovery_codes -> users (owner));
 
-allow_tables_to_appear_in_same_query!(auths, posts, users,);
+allow_tables_to_appear_in_same_query!(
+    access_tokens,
+    auths,
+    data_exports,
+    email_verifications,
+    follow_counts,
+    follow_requests,
+    follows,
+    login_challenges,
+    login_throttles,
+    oauth_clients,
+    oauth_codes,
+    password_resets,
+    posts,
+    recovery_codes,
+    used_oauth_refreshes,
+    used_refreshes,
+    users,
+);
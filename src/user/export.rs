@@ -0,0 +1,90 @@
+// Builds the archive of everything stored about a user, for data export requests.
+use super::{
+    images::{self, ImageKind},
+    structure::{DataExport, User},
+};
+use crate::{
+    auth::structure::{AccessToken, Auth, AuthSession},
+    error::StratError,
+    oauth::structure::OAuthClient,
+    post::structure::Post,
+    util::config::CONFIG,
+};
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    io::Write,
+};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+// Builds the archive of an export, marking it as ready or failed when done.
+// This blocks on the database and disk, so it should be run with spawn_blocking.
+pub fn build(mut export: DataExport) {
+    match write_archive(&export) {
+        Ok(_) => export.complete(),
+        Err(e) => {
+            eprintln!("Failed to build export {}: {}", export.get_id(), e);
+            // Don't leave a partial archive behind.
+            let _ = fs::remove_file(export.get_path());
+            export.fail();
+        }
+    }
+    if let Some(e) = export.save_export() {
+        eprintln!("Failed to save export {}: {}", export.get_id(), e);
+    }
+}
+
+// Writes one JSON file per kind of data into the export's zip, along with the user's images.
+// Posts don't keep an edit history or media yet, so only their current state is included.
+fn write_archive(export: &DataExport) -> Result<(), StratError> {
+    let user = User::get_user(export.get_owner())?;
+    let posts = Post::get_by_owner(user.get_id())?;
+    let profile = user.to_private(
+        Post::count_by_owner(user.get_id(), true)?,
+        user.get_follow_counts()?,
+    );
+    let sessions: Vec<AuthSession> = Auth::get_by_owner(user.get_id())?
+        .iter()
+        .map(|a| a.to_session(""))
+        .collect();
+    let tokens = AccessToken::get_by_owner(user.get_id())?;
+    let clients = OAuthClient::get_by_owner(user.get_id())?;
+
+    fs::create_dir_all(&CONFIG.export_dir).map_err(|_e| StratError::Unknown)?;
+    let file = File::create(export.get_path()).map_err(|_e| StratError::Unknown)?;
+    let mut zip = ZipWriter::new(file);
+    add_json(&mut zip, "profile.json", &profile)?;
+    add_json(&mut zip, "posts.json", &posts)?;
+    add_json(&mut zip, "sessions.json", &sessions)?;
+    add_json(&mut zip, "access_tokens.json", &tokens)?;
+    add_json(&mut zip, "oauth_clients.json", &clients)?;
+    for kind in [ImageKind::Avatar, ImageKind::Banner].iter() {
+        if let Some(key) = user.get_image(*kind) {
+            for (name, data) in images::load(*kind, key)? {
+                add_file(&mut zip, &format!("media/{}", name), &data)?;
+            }
+        }
+    }
+    zip.finish().map_err(|_e| StratError::Unknown)?;
+    Ok(())
+}
+
+fn add_json<T: Serialize>(
+    zip: &mut ZipWriter<File>,
+    name: &str,
+    value: &T,
+) -> Result<(), StratError> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let json = serde_json::to_vec_pretty(value).map_err(|_e| StratError::Unknown)?;
+    zip.start_file(name, options)
+        .map_err(|_e| StratError::Unknown)?;
+    zip.write_all(&json).map_err(|_e| StratError::Unknown)
+}
+
+// Images are already compressed, so they're stored as they are.
+fn add_file(zip: &mut ZipWriter<File>, name: &str, data: &[u8]) -> Result<(), StratError> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file(name, options)
+        .map_err(|_e| StratError::Unknown)?;
+    zip.write_all(data).map_err(|_e| StratError::Unknown)
+}
@@ -0,0 +1,154 @@
+// Validates, resizes and stores profile images.
+use crate::{
+    error::StratError,
+    util::{gen_random, storage::get_storage},
+};
+use image::{imageops::FilterType, io::Reader, DynamicImage, ImageFormat, ImageOutputFormat};
+use std::{collections::BTreeMap, io::Cursor};
+
+// The most pixels an upload can have in either direction, so decoding can't exhaust memory.
+const MAX_DIMENSION: u32 = 4096;
+
+// The URL of every size of an image, by the size's name.
+pub type ImageUrls = BTreeMap<&'static str, String>;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ImageKind {
+    Avatar,
+    Banner,
+}
+
+impl ImageKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageKind::Avatar => "avatar",
+            ImageKind::Banner => "banner",
+        }
+    }
+
+    // Every size the image is stored in as (name, width, height), smallest first.
+    fn sizes(self) -> &'static [(&'static str, u32, u32)] {
+        match self {
+            ImageKind::Avatar => &[("small", 48, 48), ("medium", 128, 128), ("large", 400, 400)],
+            ImageKind::Banner => &[("small", 600, 200), ("large", 1500, 500)],
+        }
+    }
+
+    // Avatars keep their transparency, banners are photos so they're smaller as JPEGs.
+    fn extension(self) -> &'static str {
+        match self {
+            ImageKind::Avatar => "png",
+            ImageKind::Banner => "jpg",
+        }
+    }
+}
+
+// Validates an uploaded image, then resizes and stores every size of it.
+// Returns the key the sizes are stored under, which is what the user keeps.
+// This is CPU heavy, so it should be run with spawn_blocking.
+pub fn store(kind: ImageKind, owner: &str, data: &[u8]) -> Result<String, StratError> {
+    let image = decode(kind, data)?;
+    let key = format!("{}-{}-{}", kind.name(), owner, gen_random(12));
+    let mut stored: Vec<String> = Vec::new();
+    for (size, width, height) in kind.sizes() {
+        let name = file_name(kind, &key, size);
+        let rslt = encode(
+            kind,
+            &image.resize_to_fill(*width, *height, FilterType::Lanczos3),
+        )
+        .and_then(|bytes| get_storage().put(&name, &bytes, content_type(kind)));
+        if let Err(e) = rslt {
+            // Don't leave half of an image behind.
+            for name in stored {
+                let _ = get_storage().delete(&name);
+            }
+            return Err(e);
+        }
+        stored.push(name);
+    }
+    Ok(key)
+}
+
+// Deletes every size of a stored image.
+pub fn remove(kind: ImageKind, key: &str) {
+    for (size, _, _) in kind.sizes() {
+        // Failures are logged by the storage, a leftover file is harmless.
+        let _ = get_storage().delete(&file_name(kind, key, size));
+    }
+}
+
+// Gets the URL of every size of a stored image.
+pub fn urls(kind: ImageKind, key: &str) -> ImageUrls {
+    kind.sizes()
+        .iter()
+        .map(|(size, _, _)| (*size, get_storage().url(&file_name(kind, key, size))))
+        .collect()
+}
+
+// Reads every size of a stored image back as (file name, bytes).
+// Sizes that are missing are skipped, like remove they're harmless.
+pub fn load(kind: ImageKind, key: &str) -> Result<Vec<(String, Vec<u8>)>, StratError> {
+    let mut files = Vec::new();
+    for (size, _, _) in kind.sizes() {
+        let name = file_name(kind, key, size);
+        if let Some(data) = get_storage().get(&name)? {
+            files.push((name, data));
+        }
+    }
+    Ok(files)
+}
+
+fn decode(kind: ImageKind, data: &[u8]) -> Result<DynamicImage, StratError> {
+    let reader = match Reader::new(Cursor::new(data)).with_guessed_format() {
+        Ok(r) => r,
+        Err(_e) => return Err(StratError::BadImage),
+    };
+    match reader.format() {
+        Some(ImageFormat::Png)
+        | Some(ImageFormat::Jpeg)
+        | Some(ImageFormat::Gif)
+        | Some(ImageFormat::WebP) => {}
+        _ => return Err(StratError::MediaUnsupported),
+    }
+    // Only the header is read to check the size, before anything is decoded.
+    let (width, height) = match reader.into_dimensions() {
+        Ok(d) => d,
+        Err(_e) => return Err(StratError::BadImage),
+    };
+    let (_, min_width, min_height) = kind.sizes()[0];
+    if width < min_width || height < min_height {
+        return Err(StratError::ImageTooSmall(min_width, min_height));
+    }
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(StratError::ImageTooLarge(MAX_DIMENSION));
+    }
+    match image::load_from_memory(data) {
+        Ok(i) => Ok(i),
+        Err(_e) => Err(StratError::BadImage),
+    }
+}
+
+fn encode(kind: ImageKind, image: &DynamicImage) -> Result<Vec<u8>, StratError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let rslt = match kind {
+        ImageKind::Avatar => image.write_to(&mut bytes, ImageOutputFormat::Png),
+        // JPEGs can't have transparency.
+        ImageKind::Banner => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut bytes, ImageOutputFormat::Jpeg(85)),
+    };
+    match rslt {
+        Ok(_) => Ok(bytes),
+        Err(_e) => Err(StratError::BadImage),
+    }
+}
+
+fn file_name(kind: ImageKind, key: &str, size: &str) -> String {
+    format!("{}-{}.{}", key, size, kind.extension())
+}
+
+fn content_type(kind: ImageKind) -> &'static str {
+    match kind {
+        ImageKind::Avatar => "image/png",
+        ImageKind::Banner => "image/jpeg",
+    }
+}
@@ -1,2 +1,5 @@
+pub mod export;
+pub mod images;
+pub mod password;
 pub mod routes;
 pub mod structure;
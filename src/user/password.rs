@@ -0,0 +1,81 @@
+// Password hashing and the policy new passwords must follow.
+use crate::{error::StratError, util::config::CONFIG, util::gen_random};
+use argon2::{Config, Variant, Version};
+use lazy_static::lazy_static;
+use std::{collections::HashSet, fs};
+
+lazy_static! {
+    // Passwords that are too common or have shown up in breaches, stored lowercase.
+    pub static ref BLOCKLIST: HashSet<String> = match &CONFIG.password_blocklist {
+        Some(path) => fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read PASSWORD_BLOCKLIST {}: {}", path, e))
+            .lines()
+            .map(|l| l.trim().to_lowercase())
+            .filter(|l| !l.is_empty())
+            .collect(),
+        None => HashSet::new(),
+    };
+}
+
+// The argon2 parameters new hashes are made with.
+fn config<'a>() -> Config<'a> {
+    Config {
+        variant: Variant::from_str(&CONFIG.argon2_variant).unwrap(),
+        version: Version::Version13,
+        mem_cost: CONFIG.argon2_mem_cost,
+        time_cost: CONFIG.argon2_time_cost,
+        lanes: CONFIG.argon2_lanes,
+        ..Config::default()
+    }
+}
+
+// Hashes a password with the configured parameters.
+pub fn hash(password: &str) -> String {
+    let salt = gen_random(30);
+    argon2::hash_encoded(password.as_ref(), salt.as_ref(), &config()).unwrap()
+}
+
+// Checks if the submitted password matches a hash, malformed hashes never match.
+pub fn verify(password: &str, encoded: &str) -> bool {
+    argon2::verify_encoded(encoded, password.as_ref()).unwrap_or(false)
+}
+
+// Checks if a hash was made with different parameters than the configured ones,
+// meaning it should be replaced the next time the password is known.
+pub fn needs_rehash(encoded: &str) -> bool {
+    let config = config();
+    // Encoded hashes look like $argon2id$v=19$m=19456,t=2,p=1$salt$hash
+    let parts: Vec<&str> = encoded.split('$').collect();
+    if parts.len() != 6 {
+        return true;
+    }
+    let expected = format!(
+        "m={},t={},p={}",
+        config.mem_cost, config.time_cost, config.lanes
+    );
+    parts[1] != config.variant.as_lowercase_str()
+        || parts[2] != format!("v={}", config.version.as_u32())
+        || parts[3] != expected
+}
+
+// Checks a new password against the policy, the email and nickname are those of its account.
+pub fn check_policy(password: &str, email: &str, nickname: &str) -> Result<(), StratError> {
+    if password.chars().count() < CONFIG.password_min_length {
+        return Err(StratError::PasswordTooShort(CONFIG.password_min_length));
+    }
+    let lowered = password.to_lowercase();
+    if BLOCKLIST.contains(&lowered) {
+        return Err(StratError::PasswordCommon);
+    }
+    // The local part of the email is checked too, but very short ones would match too much.
+    let email = email.to_lowercase();
+    let local = email.split('@').next().unwrap_or("");
+    let nickname = nickname.to_lowercase();
+    if (!email.is_empty() && lowered.contains(&email))
+        || (local.len() >= 3 && lowered.contains(local))
+        || (!nickname.is_empty() && lowered.contains(&nickname))
+    {
+        return Err(StratError::PasswordPersonal);
+    }
+    Ok(())
+}
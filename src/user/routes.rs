@@ -1,9 +1,26 @@
-use super::structure::{User, UserCreatable};
+use super::{
+    export,
+    images::{self, ImageKind},
+    password,
+    structure::{DataExport, EmailVerification, PasswordReset, User, UserCreatable},
+};
 use crate::{
+    auth::{
+        permissions::authorize_self,
+        routes::{clear_auth_cookies, require_session},
+        structure::{Auth, Scopes},
+    },
     error::StratError,
-    util::{json_response, parse_body},
+    follow::structure::FollowRequest,
+    post::structure::Post,
+    util::{config::CONFIG, get_query, json_response, mail::get_mailer, parse_body},
+};
+use hyper::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    Body, Request, Response,
 };
-use hyper::{Body, Request, Response};
+use multer::{Constraints, Multipart, SizeLimit};
+use routerify::ext::RequestExt;
 
 // Create an instance of a User, and saves it to the database.
 // Takes the UserCreatable struct as the body ex: {"nickname": "testaccount", "email": "johndoe@example.com", "password": "password"}
@@ -12,11 +29,522 @@ pub async fn create_user(mut req: Request<Body>) -> Result<Response<Body>, Strat
         Ok(val) => val,
         Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
     };
+    User::check_nickname(&u.nickname)?;
+    password::check_policy(&u.password, &u.email, &u.nickname)?;
     let mut user = User::new(u.nickname, u.email, u.password);
+    user.save_user()?;
+    // The account exists either way, if the mail fails it can be sent again later.
+    if let Err(e) = send_verification(&user, user.get_email()) {
+        eprintln!("Failed to send verification to {}: {}", user.get_id(), e);
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": "Successfully created user!"}),
+    ))
+}
+
+// Verifies an email address using the token that was mailed to it.
+// Takes the token as a query parameter ex: /user/verify?token=ABCDEFGHIJ...
+pub async fn verify_email(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let token = match get_query(&req, "token") {
+        Some(t) => t,
+        None => return Err(StratError::UnknownVerification),
+    };
+    let verification = match EmailVerification::consume(&token) {
+        Ok(v) => v,
+        Err(e) => return Err(e),
+    };
+    let mut user = match User::get_user(verification.get_owner()) {
+        Ok(u) => u,
+        Err(e) => return Err(e),
+    };
+    user.verify_email(verification.get_email());
     match user.save_user() {
         Ok(_) => Ok(json_response(
-            json!({"status": 200, "response": "Successfully created user!"}),
+            json!({"status": 200, "response": "Email successfully verified!"}),
         )),
         Err(e) => Err(e),
     }
 }
+
+// Sends the authenticated user a new verification token.
+pub async fn resend_verification(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    if user.is_verified() {
+        return Ok(json_response(
+            json!({"status": 200, "response": "Email is already verified!"}),
+        ));
+    }
+    match send_verification(&user, user.get_email()) {
+        Ok(_) => Ok(json_response(
+            json!({"status": 200, "response": "Verification email sent!"}),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+// Creates a verification for an address and mails its token there.
+fn send_verification(user: &User, email: &str) -> Result<(), StratError> {
+    let (verification, token) = EmailVerification::new(user.get_id().to_owned(), email.to_owned());
+    if let Some(e) = verification.save_verification() {
+        return Err(e);
+    }
+    let body = format!(
+        "Please confirm that this address belongs to your Stratosphere account.\n\n\
+         Your verification token is: {}\n\n\
+         It expires in one day. If you didn't request this, you can ignore this email.",
+        token
+    );
+    get_mailer().send(email, "Verify your Stratosphere email", &body)
+}
+
+// Sends a password reset token to the email of an account.
+// Takes an email as the body ex: {"email": "johndoe@example.com"}
+pub async fn forgot_password(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct PasswordForgot {
+        email: String,
+    }
+
+    let p: PasswordForgot = match parse_body::<PasswordForgot>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    // The response is the same whether or not the account exists,
+    // so this can't be used to find out which emails are registered.
+    let response = json_response(
+        json!({"status": 200, "response": "If an account uses that email, a reset token has been sent to it."}),
+    );
+    let user = match User::get_by_email(&p.email) {
+        Ok(u) => u,
+        Err(StratError::UserNotFound) => return Ok(response),
+        Err(e) => return Err(e),
+    };
+    match send_reset(&user) {
+        Ok(_) => Ok(response),
+        Err(e) => Err(e),
+    }
+}
+
+// Creates a password reset for a user and mails its token to them.
+pub fn send_reset(user: &User) -> Result<(), StratError> {
+    let (reset, token) = PasswordReset::new(user.get_id().to_owned());
+    if let Some(e) = reset.save_reset() {
+        return Err(e);
+    }
+    let body = format!(
+        "A password reset was requested for your Stratosphere account.\n\n\
+         Your reset token is: {}\n\n\
+         It expires in one hour. If you didn't request this, you can ignore this email.",
+        token
+    );
+    get_mailer().send(user.get_email(), "Reset your Stratosphere password", &body)
+}
+
+// Sets a new password using a reset token, ending every session of the account.
+// Takes a token and password as the body ex: {"token": "ABCDEFGHIJ...", "password": "password"}
+pub async fn reset_password(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct PasswordResetBody {
+        token: String,
+        password: String,
+    }
+
+    let p: PasswordResetBody = match parse_body::<PasswordResetBody>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    let owner = match PasswordReset::consume(&p.token) {
+        Ok(o) => o,
+        Err(e) => return Err(e),
+    };
+    let mut user = match User::get_user(&owner) {
+        Ok(u) => u,
+        Err(e) => return Err(e),
+    };
+    user.set_password(&p.password)?;
+    user.save_user()?;
+    Auth::delete_all_for(user.get_id())?;
+    Ok(json_response(
+        json!({"status": 200, "response": "Password successfully reset!"}),
+    ))
+}
+
+// Gets the public profile of a user using their nickname.
+pub async fn get_profile(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let user = match User::get_by_nickname(req.param("nickname").unwrap()) {
+        Ok(u) => u,
+        Err(e) => return Err(e),
+    };
+    public_profile(&user)
+}
+
+// Gets the public profile of a user using their ID.
+pub async fn get_profile_by_id(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let user = match User::get_user(req.param("id").unwrap()) {
+        Ok(u) => u,
+        Err(e) => return Err(e),
+    };
+    public_profile(&user)
+}
+
+// Gets the private profile of the authenticated user.
+pub async fn get_me(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    req.context::<Scopes>().unwrap().require("profile:read")?;
+    let user = req.context::<User>().unwrap();
+    let posts = Post::count_by_owner(user.get_id(), true)?;
+    Ok(json_response(
+        json!({"status": 200, "response": user.to_private(posts, user.get_follow_counts()?)}),
+    ))
+}
+
+// Edits the authenticated user's profile, fields that are left out aren't changed
+// and empty ones are cleared (except the nickname, which can't be empty).
+// Takes any of the profile fields as the body
+// ex: {"nickname": "johndoe", "display_name": "John Doe", "bio": "Hello!", "location": "Earth", "website": "https://example.com", "protected": true}
+// Unprotecting an account approves every follow request it has waiting.
+pub async fn update_me(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct ProfileUpdate {
+        nickname: Option<String>,
+        display_name: Option<String>,
+        bio: Option<String>,
+        location: Option<String>,
+        website: Option<String>,
+        protected: Option<bool>,
+    }
+
+    require_session(&req)?;
+    let mut user = req.context::<User>().unwrap();
+    let p: ProfileUpdate = match parse_body::<ProfileUpdate>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if let Some(nickname) = &p.nickname {
+        user.set_nickname(nickname)?;
+    }
+    if let Some(display_name) = &p.display_name {
+        user.set_display_name(display_name)?;
+    }
+    if let Some(bio) = &p.bio {
+        user.set_bio(bio)?;
+    }
+    if let Some(location) = &p.location {
+        user.set_location(location)?;
+    }
+    if let Some(website) = &p.website {
+        user.set_website(website)?;
+    }
+    let unprotecting = p.protected == Some(false) && user.is_protected();
+    if let Some(protected) = p.protected {
+        user.set_protected(protected);
+    }
+    user.save_user()?;
+    if unprotecting {
+        // Saving the user above never touches the counts these approvals change.
+        FollowRequest::approve_all(user.get_id())?;
+    }
+    let posts = Post::count_by_owner(user.get_id(), true)?;
+    Ok(json_response(
+        json!({"status": 200, "response": user.to_private(posts, user.get_follow_counts()?)}),
+    ))
+}
+
+// Changes the authenticated user's password, logging out every other session.
+// Takes the current and new password as the body ex: {"current_password": "password", "password": "newpassword"}
+pub async fn change_password(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct PasswordChange {
+        current_password: String,
+        password: String,
+    }
+
+    let auth = require_session(&req)?;
+    let mut user = req.context::<User>().unwrap();
+    let p: PasswordChange = match parse_body::<PasswordChange>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if !user.check_password(&p.current_password) {
+        return Err(StratError::WrongPassword);
+    }
+    user.set_password(&p.password)?;
+    user.save_user()?;
+    let count = Auth::delete_others_for(user.get_id(), auth.get_id())?;
+    Ok(json_response(
+        json!({"status": 200, "response": "Password successfully changed!", "revoked": count}),
+    ))
+}
+
+// Starts changing the authenticated user's email, the new address only replaces
+// the current one once it's been confirmed with the token mailed to it.
+// Takes the new email and current password as the body ex: {"email": "janedoe@example.com", "password": "password"}
+pub async fn change_email(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct EmailChange {
+        email: String,
+        password: String,
+    }
+
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    let e: EmailChange = match parse_body::<EmailChange>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if !user.check_password(&e.password) {
+        return Err(StratError::WrongPassword);
+    }
+    // Checked up front so nobody is mailed for an address they can't have,
+    // the unique key still catches anyone taking it before it's confirmed.
+    match User::get_by_email(&e.email) {
+        Ok(_u) => return Err(StratError::EmailInUse),
+        Err(StratError::UserNotFound) => {}
+        Err(e) => return Err(e),
+    }
+    send_verification(&user, &e.email)?;
+    let notice = format!(
+        "A change of your Stratosphere email to {} was requested.\n\n\
+         It only takes effect once confirmed from the new address. \
+         If this wasn't you, please change your password.",
+        e.email
+    );
+    if let Err(err) = get_mailer().send(
+        user.get_email(),
+        "Your Stratosphere email is changing",
+        &notice,
+    ) {
+        eprintln!(
+            "Failed to notify {} of an email change: {}",
+            user.get_id(),
+            err
+        );
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": "A confirmation has been sent to the new address!"}),
+    ))
+}
+
+// Deletes the authenticated user along with everything they own.
+// If there's a grace period, the account is only scheduled for deletion and
+// every session is ended, logging in again before it's over cancels it.
+// Takes the current password as the body ex: {"password": "password"}
+pub async fn delete_me(mut req: Request<Body>) -> Result<Response<Body>, StratError> {
+    #[derive(Deserialize)]
+    struct AccountDelete {
+        password: String,
+    }
+
+    require_session(&req)?;
+    let mut user = req.context::<User>().unwrap();
+    let d: AccountDelete = match parse_body::<AccountDelete>(&mut req).await {
+        Ok(val) => val,
+        Err(e) => return Ok(json_response(json!({"status": 500, "response": e}))),
+    };
+    if !user.check_password(&d.password) {
+        return Err(StratError::WrongPassword);
+    }
+    let mut response = if CONFIG.deletion_grace_days > 0 {
+        user.schedule_deletion(chrono::Duration::days(CONFIG.deletion_grace_days))?;
+        json_response(json!({
+            "status": 200,
+            "response": "Account scheduled for deletion! Log in again before then to cancel.",
+            "deletion_scheduled": user.get_deletion_scheduled()
+        }))
+    } else {
+        if let Some(e) = user.delete_user() {
+            return Err(e);
+        }
+        json_response(json!({"status": 200, "response": "Account deleted!"}))
+    };
+    clear_auth_cookies(&mut response);
+    Ok(response)
+}
+
+// Starts building an archive of everything stored about the authenticated user.
+// If one is already being built, that one is returned instead of starting another.
+pub async fn request_export(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let user = req.context::<User>().unwrap();
+    if let Some(pending) = DataExport::get_pending_for(user.get_id())? {
+        return Ok(json_response(json!({"status": 200, "response": pending})));
+    }
+    let data_export = DataExport::new(user.get_id().to_owned());
+    if let Some(e) = data_export.save_export() {
+        return Err(e);
+    }
+    let response = json_response(json!({"status": 200, "response": &data_export}));
+    tokio::task::spawn_blocking(move || export::build(data_export));
+    Ok(response)
+}
+
+// Gets the status of one of the authenticated user's exports.
+// Once it's ready, this includes the link to download it from until it expires.
+pub async fn export_status(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let data_export = get_own_export(&req)?;
+    let download = if data_export.is_ready() {
+        Some(format!("/v1/me/export/{}/download", data_export.get_id()))
+    } else {
+        None
+    };
+    Ok(json_response(
+        json!({"status": 200, "response": data_export, "download": download}),
+    ))
+}
+
+// Downloads the archive of one of the authenticated user's exports.
+pub async fn download_export(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let data_export = get_own_export(&req)?;
+    if !data_export.is_ready() {
+        return Err(StratError::ExportNotReady);
+    }
+    let archive = match tokio::fs::read(data_export.get_path()).await {
+        Ok(a) => a,
+        Err(_e) => return Err(StratError::ExportNotReady),
+    };
+    let disposition = format!(
+        "attachment; filename=\"stratosphere-export-{}.zip\"",
+        data_export.get_id()
+    );
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/zip")
+        .header(CONTENT_DISPOSITION, disposition)
+        .body(Body::from(archive))
+        .unwrap())
+}
+
+// Gets the export named in the path, as long as it belongs to the authenticated user.
+fn get_own_export(req: &Request<Body>) -> Result<DataExport, StratError> {
+    require_session(req)?;
+    let user = req.context::<User>().unwrap();
+    let data_export = DataExport::get_by_id(req.param("id").unwrap())?;
+    authorize_self(&user, data_export.get_owner(), StratError::UnknownExport)?;
+    Ok(data_export)
+}
+
+// Sets the authenticated user's avatar using Multipart Form Data with an "image" field.
+pub async fn upload_avatar(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    upload_image(req, ImageKind::Avatar).await
+}
+
+// Sets the authenticated user's banner using Multipart Form Data with an "image" field.
+pub async fn upload_banner(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    upload_image(req, ImageKind::Banner).await
+}
+
+// Removes the authenticated user's avatar.
+pub async fn delete_avatar(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    remove_image(req, ImageKind::Avatar)
+}
+
+// Removes the authenticated user's banner.
+pub async fn delete_banner(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    remove_image(req, ImageKind::Banner)
+}
+
+async fn upload_image(req: Request<Body>, kind: ImageKind) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let mut user = req.context::<User>().unwrap();
+    let boundary = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|ct| ct.to_str().ok())
+        .and_then(|ct| multer::parse_boundary(ct).ok());
+
+    let constraints = Constraints::new()
+        // Only allow the image
+        .allowed_fields(vec!["image"])
+        .size_limit(
+            SizeLimit::new()
+                // Set 9mb as size limit for the whole stream body.
+                .whole_stream(9 * 1024 * 1024)
+                // Set 8mb as size limit for the image.
+                .per_field(8 * 1024 * 1024),
+        );
+    if boundary.is_none() {
+        return Err(StratError::BadMulti);
+    }
+    let data = parse_image(req.into_body(), boundary.unwrap(), constraints).await?;
+    // Decoding and resizing would hold up the async workers.
+    let owner = user.get_id().to_owned();
+    let key = match tokio::task::spawn_blocking(move || images::store(kind, &owner, &data)).await {
+        Ok(k) => k?,
+        Err(_e) => return Err(StratError::Unknown),
+    };
+    let old = user.set_image(kind, Some(key.clone()));
+    if let Err(e) = user.save_user() {
+        images::remove(kind, &key);
+        return Err(e);
+    }
+    if let Some(old) = old {
+        images::remove(kind, &old);
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": images::urls(kind, &key)}),
+    ))
+}
+
+fn remove_image(req: Request<Body>, kind: ImageKind) -> Result<Response<Body>, StratError> {
+    require_session(&req)?;
+    let mut user = req.context::<User>().unwrap();
+    if let Some(old) = user.set_image(kind, None) {
+        user.save_user()?;
+        images::remove(kind, &old);
+    }
+    Ok(json_response(
+        json!({"status": 200, "response": format!("Successfully removed {}!", kind.name())}),
+    ))
+}
+
+async fn parse_image(
+    body: Body,
+    boundary: String,
+    constraints: Constraints,
+) -> Result<Vec<u8>, StratError> {
+    let mut multipart = Multipart::new_with_constraints(body, boundary, constraints);
+    let mut image: Option<Vec<u8>> = None;
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) => {
+                // The contents are checked when decoding, this just turns away obvious mistakes.
+                let is_image = field
+                    .content_type()
+                    .map(|ct| ct.type_() == mime::IMAGE)
+                    .unwrap_or(false);
+                if !is_image {
+                    return Err(StratError::MediaUnsupported);
+                }
+                // If multiple images are sent, the last one is used.
+                image = match field.bytes().await {
+                    Ok(b) => Some(b.to_vec()),
+                    Err(multer::Error::FieldSizeExceeded { limit, field_name })
+                        if field_name.is_some() =>
+                    {
+                        return Err(StratError::OversizedField(field_name.unwrap(), limit))
+                    }
+                    Err(_e) => return Err(StratError::BadMulti),
+                };
+            }
+            Ok(None) => break,
+            Err(multer::Error::FieldSizeExceeded { limit, field_name }) if field_name.is_some() => {
+                return Err(StratError::OversizedField(field_name.unwrap(), limit))
+            }
+            Err(_e) => return Err(StratError::BadMulti),
+        }
+    }
+    match image {
+        Some(i) => Ok(i),
+        None => Err(StratError::BadImage),
+    }
+}
+
+fn public_profile(user: &User) -> Result<Response<Body>, StratError> {
+    let posts = if user.are_posts_hidden() {
+        0
+    } else {
+        Post::count_by_owner(user.get_id(), false)?
+    };
+    Ok(json_response(
+        json!({"status": 200, "response": user.to_public(posts, user.get_follow_counts()?)}),
+    ))
+}
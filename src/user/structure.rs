@@ -1,16 +1,43 @@
-use crate::schema::users::dsl as user_dsl;
+use super::{
+    images::{self, ImageKind, ImageUrls},
+    password,
+};
+use crate::schema::{
+    access_tokens::dsl as access_dsl, auths::dsl as auth_dsl, data_exports::dsl as export_dsl,
+    email_verifications::dsl as verify_dsl, follow_counts::dsl as count_dsl,
+    follows::dsl as follow_dsl, login_throttles::dsl as throttle_dsl,
+    password_resets::dsl as reset_dsl, users::dsl as user_dsl,
+};
 use crate::util::{
+    config::CONFIG,
     db::{can_connect, get_database},
-    gen_random,
+    gen_random, hash_token,
+};
+use crate::{
+    auth::{permissions::Role, totp},
+    error::StratError,
+    follow::structure::FollowCounts,
+    schema::{data_exports, email_verifications, password_resets, users},
 };
-use crate::{error::StratError, schema::users};
-use argon2::{self, Config};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use diesel::{
-    result::Error as dsl_err, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    result::Error as dsl_err, BoolExpressionMethods, Connection, ExpressionMethods,
+    OptionalExtension, PgConnection, PgTextExpressionMethods, QueryDsl, QueryResult, RunQueryDsl,
 };
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// How many users a search returns at once.
+const SEARCH_PAGE_SIZE: i64 = 50;
 
-#[derive(Queryable, Insertable, Serialize, Deserialize, Debug, AsChangeset, Clone)]
+// Never serialize this directly, it holds the password hash and email.
+// Use PublicProfile or PrivateProfile instead.
+#[derive(Queryable, Insertable, Deserialize, Debug, AsChangeset, Clone)]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct User {
     id: String,
     nickname: String,
@@ -20,6 +47,22 @@ pub struct User {
     is_priv: bool,
     updated_at: NaiveDateTime,
     created_at: NaiveDateTime,
+    verified: bool,
+    totp_secret: Option<String>,
+    totp_enabled: bool,
+    totp_last_step: i64,
+    display_name: Option<String>,
+    bio: Option<String>,
+    location: Option<String>,
+    website: Option<String>,
+    nickname_changed: Option<NaiveDateTime>,
+    deletion_scheduled: Option<NaiveDateTime>,
+    avatar: Option<String>,
+    banner: Option<String>,
+    status: String,
+    status_until: Option<NaiveDateTime>,
+    status_reason: Option<String>,
+    protected: bool,
 }
 
 impl User {
@@ -30,11 +73,27 @@ impl User {
             id: gen_random(23),
             nickname,
             email,
-            password: Self::hash_pass(&password),
+            password: password::hash(&password),
             rank: 0,
             is_priv: false,
             updated_at: time.naive_utc(),
             created_at: time.naive_utc(),
+            verified: false,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_step: 0,
+            display_name: None,
+            bio: None,
+            location: None,
+            website: None,
+            nickname_changed: None,
+            deletion_scheduled: None,
+            avatar: None,
+            banner: None,
+            status: "active".to_owned(),
+            status_until: None,
+            status_reason: None,
+            protected: false,
         }
     }
 
@@ -57,6 +116,69 @@ impl User {
         }
     }
 
+    // Gets an instance of the user using their email
+    pub fn get_by_email(email: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let user: QueryResult<User> = user_dsl::users
+                .filter(user_dsl::email.eq(email))
+                .first::<User>(db);
+            match user {
+                Ok(u) => Ok(u),
+                Err(_e) => Err(StratError::UserNotFound),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Finds users whose nickname or email contains the query, newest first.
+    pub fn search(query: &str, page: i64) -> Result<Vec<Self>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            // The query is matched literally, so LIKE wildcards in it are escaped.
+            let pattern = format!(
+                "%{}%",
+                query
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            );
+            let users: QueryResult<Vec<User>> = user_dsl::users
+                .filter(
+                    user_dsl::nickname
+                        .ilike(&pattern)
+                        .or(user_dsl::email.ilike(&pattern)),
+                )
+                .order(user_dsl::created_at.desc())
+                .limit(SEARCH_PAGE_SIZE)
+                .offset(page.max(0) * SEARCH_PAGE_SIZE)
+                .load::<User>(db);
+            match users {
+                Ok(u) => Ok(u),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Gets an instance of the user using their nickname
+    pub fn get_by_nickname(nickname: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let user: QueryResult<User> = user_dsl::users
+                .filter(user_dsl::nickname.eq(nickname))
+                .first::<User>(db);
+            match user {
+                Ok(u) => Ok(u),
+                Err(_e) => Err(StratError::UserNotFound),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
     // Find a user using the username and password combination
     pub fn get_by_login(email: &str, password: &str) -> Result<Self, StratError> {
         if can_connect() {
@@ -64,14 +186,23 @@ impl User {
             let query: QueryResult<User> = user_dsl::users
                 .filter(user_dsl::email.eq(email))
                 .first::<User>(db);
-            let user = match query {
+            let mut user = match query {
                 Ok(u) => u,
                 Err(_e) => return Err(StratError::BadLogin),
             };
-            if Self::verify_pass(password, &user.password) {
-                return Ok(user);
+            if !password::verify(password, &user.password) {
+                return Err(StratError::BadLogin);
+            }
+            // Only checked once the password is right, so this can't be used to probe accounts.
+            user.check_active()?;
+            // Hashes made with older parameters are upgraded while the password is known.
+            if password::needs_rehash(&user.password) {
+                user.password = password::hash(password);
+                if let Err(e) = user.save_user() {
+                    eprintln!("Failed to rehash the password of {}: {}", user.id, e);
+                }
             }
-            Err(StratError::BadLogin)
+            Ok(user)
         } else {
             Err(StratError::DbFailed)
         }
@@ -79,12 +210,112 @@ impl User {
 
     //setters
 
-    pub fn set_nickname(&mut self, nickname: &str) {
+    // Changes the user's nickname, which can only be done once every NICKNAME_CHANGE_DAYS.
+    pub fn set_nickname(&mut self, nickname: &str) -> Result<(), StratError> {
+        if nickname == self.nickname {
+            return Ok(());
+        }
+        Self::check_nickname(nickname)?;
+        let now = Utc::now().naive_utc();
+        if let Some(changed) = self.nickname_changed {
+            let next = changed + Duration::days(CONFIG.nickname_change_days);
+            if next > now {
+                // Rounded up, so it never says 0 days.
+                let days = ((next - now).num_hours() + 23) / 24;
+                return Err(StratError::NicknameCooldown(days.max(1)));
+            }
+        }
         self.nickname = nickname.to_owned();
+        self.nickname_changed = Some(now);
+        Ok(())
+    }
+
+    // An empty value clears the field.
+    pub fn set_display_name(&mut self, display_name: &str) -> Result<(), StratError> {
+        self.display_name = Self::check_field("display_name", display_name, 50)?;
+        Ok(())
+    }
+
+    pub fn set_bio(&mut self, bio: &str) -> Result<(), StratError> {
+        self.bio = Self::check_field("bio", bio, 300)?;
+        Ok(())
+    }
+
+    pub fn set_location(&mut self, location: &str) -> Result<(), StratError> {
+        self.location = Self::check_field("location", location, 50)?;
+        Ok(())
+    }
+
+    // Protected users approve their followers, and only they can see their posts.
+    pub fn set_protected(&mut self, protected: bool) {
+        self.protected = protected;
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.protected
+    }
+
+    // Only http and https links are allowed, so it's safe to show as a link.
+    pub fn set_website(&mut self, website: &str) -> Result<(), StratError> {
+        let website = Self::check_field("website", website, 100)?;
+        if let Some(w) = &website {
+            let lowered = w.to_lowercase();
+            if !(lowered.starts_with("https://") || lowered.starts_with("http://"))
+                || w.chars().any(char::is_whitespace)
+            {
+                return Err(StratError::InvalidWebsite);
+            }
+        }
+        self.website = website;
+        Ok(())
+    }
+
+    // Changes the user's password, as long as the new one follows the password policy.
+    pub fn set_password(&mut self, password: &str) -> Result<(), StratError> {
+        password::check_policy(password, &self.email, &self.nickname)?;
+        self.password = password::hash(password);
+        Ok(())
+    }
+
+    // Starts enrolling two-factor authentication with a new secret, returning it.
+    // It isn't enforced until a code from it has been confirmed with enable_totp.
+    pub fn begin_totp(&mut self) -> String {
+        let secret = totp::generate_secret();
+        self.totp_secret = Some(secret.clone());
+        self.totp_enabled = false;
+        self.totp_last_step = 0;
+        secret
+    }
+
+    pub fn enable_totp(&mut self) {
+        self.totp_enabled = true;
+    }
+
+    pub fn disable_totp(&mut self) {
+        self.totp_secret = None;
+        self.totp_enabled = false;
+        self.totp_last_step = 0;
+    }
+
+    // Checks a code from the user's authenticator, marking it as used if it's valid.
+    pub fn verify_totp(&mut self, code: &str) -> bool {
+        let secret = match &self.totp_secret {
+            Some(s) => s,
+            None => return false,
+        };
+        match totp::verify(secret, code, self.totp_last_step) {
+            Some(step) => {
+                self.totp_last_step = step;
+                true
+            }
+            None => false,
+        }
     }
 
-    pub fn set_password(&mut self, password: &str) {
-        self.password = Self::hash_pass(password);
+    // Marks an email as belonging to this user, making it their address if it isn't already.
+    pub fn verify_email(&mut self, email: &str) {
+        self.email = email.to_owned();
+        self.verified = true;
     }
 
     // Saves our user back into the database.
@@ -108,7 +339,272 @@ impl User {
         Err(StratError::DbFailed)
     }
 
+    // Sets the key of one of the user's images, returning the key it replaced.
+    pub fn set_image(&mut self, kind: ImageKind, key: Option<String>) -> Option<String> {
+        match kind {
+            ImageKind::Avatar => std::mem::replace(&mut self.avatar, key),
+            ImageKind::Banner => std::mem::replace(&mut self.banner, key),
+        }
+    }
+
+    // Gets the key one of the user's images is stored under, if they have it.
+    pub fn get_image(&self, kind: ImageKind) -> Option<&str> {
+        match kind {
+            ImageKind::Avatar => self.avatar.as_deref(),
+            ImageKind::Banner => self.banner.as_deref(),
+        }
+    }
+
+    // Gets the URLs of every size of the user's avatar and banner.
+    fn get_image_urls(&self) -> (Option<ImageUrls>, Option<ImageUrls>) {
+        (
+            self.avatar
+                .as_ref()
+                .map(|key| images::urls(ImageKind::Avatar, key)),
+            self.banner
+                .as_ref()
+                .map(|key| images::urls(ImageKind::Banner, key)),
+        )
+    }
+
+    // Changes the user's role, staff are marked is_priv so Role::of picks up their rank.
+    pub fn set_role(&mut self, role: Role) {
+        self.rank = role.rank();
+        self.is_priv = role != Role::User;
+    }
+
+    // Gets the state of the user's account, suspensions that have run out count as active.
+    pub fn get_status(&self) -> AccountStatus {
+        match self.status.as_str() {
+            "suspended" => match self.status_until {
+                Some(until) if until <= Utc::now().naive_utc() => AccountStatus::Active,
+                until => AccountStatus::Suspended(until),
+            },
+            "banned" => AccountStatus::Banned,
+            "deactivated" => AccountStatus::Deactivated,
+            _ => AccountStatus::Active,
+        }
+    }
+
+    // Checks the user's account is active, returning why it isn't if it's not.
+    pub fn check_active(&self) -> Result<(), StratError> {
+        match self.get_status() {
+            AccountStatus::Active => Ok(()),
+            AccountStatus::Suspended(until) => Err(StratError::AccountSuspended(
+                until,
+                self.status_reason.clone(),
+            )),
+            AccountStatus::Banned => Err(StratError::AccountBanned(self.status_reason.clone())),
+            AccountStatus::Deactivated => Err(StratError::AccountDeactivated),
+        }
+    }
+
+    // Posts of accounts that aren't active are hidden from everyone else.
+    pub fn are_posts_hidden(&self) -> bool {
+        self.get_status() != AccountStatus::Active
+    }
+
+    // Changes the state of the user's account, the reason is shown to them when they're refused.
+    pub fn set_status(&mut self, status: AccountStatus, reason: Option<String>) {
+        let (name, until) = match status {
+            AccountStatus::Active => ("active", None),
+            AccountStatus::Suspended(until) => ("suspended", until),
+            AccountStatus::Banned => ("banned", None),
+            AccountStatus::Deactivated => ("deactivated", None),
+        };
+        self.status = name.to_owned();
+        self.status_until = until;
+        self.status_reason = match status {
+            AccountStatus::Active => None,
+            _ => reason,
+        };
+    }
+
+    // Replaces the user's password with one nobody knows, so it has to be reset.
+    pub fn scramble_password(&mut self) {
+        self.password = password::hash(&gen_random(64));
+    }
+
+    // Marks the user to be deleted once the grace period is over, ending every
+    // session and access token in the same transaction.
+    // Logging in before then cancels it.
+    pub fn schedule_deletion(&mut self, grace: Duration) -> Result<(), StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let now = Utc::now().naive_utc();
+            let scheduled = now + grace;
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                diesel::update(user_dsl::users.find(&self.id))
+                    .set((
+                        user_dsl::deletion_scheduled.eq(scheduled),
+                        user_dsl::updated_at.eq(now),
+                    ))
+                    .execute(db)?;
+                diesel::delete(auth_dsl::auths.filter(auth_dsl::owner.eq(&self.id))).execute(db)?;
+                diesel::delete(access_dsl::access_tokens.filter(access_dsl::owner.eq(&self.id)))
+                    .execute(db)?;
+                Ok(())
+            });
+            match rslt {
+                Ok(_) => {
+                    self.deletion_scheduled = Some(scheduled);
+                    self.updated_at = now;
+                    Ok(())
+                }
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Cancels a scheduled deletion, returning whether there was one.
+    pub fn cancel_deletion(&mut self) -> bool {
+        self.deletion_scheduled.take().is_some()
+    }
+
+    pub fn get_deletion_scheduled(&self) -> Option<NaiveDateTime> {
+        self.deletion_scheduled
+    }
+
+    // Deletes the user, consuming self.
+    // Everything they own goes with them through the owner foreign keys.
+    pub fn delete_user(self) -> Option<StratError> {
+        Self::delete_by_id(&self.id, false).err()
+    }
+
+    // Deletes every user whose grace period is over, returning how many were removed.
+    pub fn delete_due() -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let due: QueryResult<Vec<String>> = user_dsl::users
+                .filter(user_dsl::deletion_scheduled.lt(Utc::now().naive_utc()))
+                .select(user_dsl::id)
+                .load::<String>(db);
+            let due = match due {
+                Ok(d) => d,
+                Err(e) => return Err(Self::match_errors(e)),
+            };
+            let mut count = 0;
+            for id in due {
+                match Self::delete_by_id(&id, true) {
+                    Ok(true) => count += 1,
+                    Ok(false) => {}
+                    Err(e) => eprintln!("Failed to delete user {}: {}", id, e),
+                }
+            }
+            Ok(count)
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Deletes a user in one transaction, returning whether they were deleted.
+    // The user is locked first, and with only_due they're only deleted if their
+    // deletion is still due, so logging in right before the sweep still cancels it.
+    fn delete_by_id(id: &str, only_due: bool) -> Result<bool, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                let user: Option<Self> = if only_due {
+                    user_dsl::users
+                        .find(id)
+                        .filter(user_dsl::deletion_scheduled.lt(Utc::now().naive_utc()))
+                        .for_update()
+                        .first::<Self>(db)
+                        .optional()?
+                } else {
+                    user_dsl::users
+                        .find(id)
+                        .for_update()
+                        .first::<Self>(db)
+                        .optional()?
+                };
+                let user = match user {
+                    Some(u) => u,
+                    None => return Ok(None),
+                };
+                // Login throttles are keyed by email rather than owner, so they don't cascade.
+                diesel::delete(
+                    throttle_dsl::login_throttles
+                        .filter(throttle_dsl::scope.eq("account"))
+                        .filter(throttle_dsl::key.eq(user.email.trim().to_lowercase())),
+                )
+                .execute(db)?;
+                // Follows cascade, but the counts of whoever was on the other side don't.
+                diesel::update(
+                    count_dsl::follow_counts.filter(
+                        count_dsl::user_id.eq_any(
+                            follow_dsl::follows
+                                .select(follow_dsl::followee)
+                                .filter(follow_dsl::follower.eq(&user.id)),
+                        ),
+                    ),
+                )
+                .set(count_dsl::followers.eq(count_dsl::followers - 1))
+                .execute(db)?;
+                diesel::update(
+                    count_dsl::follow_counts.filter(
+                        count_dsl::user_id.eq_any(
+                            follow_dsl::follows
+                                .select(follow_dsl::follower)
+                                .filter(follow_dsl::followee.eq(&user.id)),
+                        ),
+                    ),
+                )
+                .set(count_dsl::following.eq(count_dsl::following - 1))
+                .execute(db)?;
+                diesel::delete(user_dsl::users.find(&user.id)).execute(db)?;
+                Ok(Some(user))
+            });
+            match rslt {
+                Ok(Some(user)) => {
+                    // Files aren't part of the transaction, so they go once the user is gone.
+                    if let Some(key) = &user.avatar {
+                        images::remove(ImageKind::Avatar, key);
+                    }
+                    if let Some(key) = &user.banner {
+                        images::remove(ImageKind::Banner, key);
+                    }
+                    Ok(true)
+                }
+                Ok(None) => Ok(false),
+                Err(e) => Err(Self::match_errors(e)),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
     // util
+    // Checks a nickname is 3 to 32 letters, numbers or underscores and isn't reserved.
+    pub fn check_nickname(nickname: &str) -> Result<(), StratError> {
+        // These would be shadowed by other routes under /user/.
+        const RESERVED: [&str; 5] = ["create", "id", "login", "password", "verify"];
+        let valid_chars = nickname
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !(3..=32).contains(&nickname.len())
+            || !valid_chars
+            || RESERVED.contains(&nickname.to_lowercase().as_str())
+        {
+            return Err(StratError::InvalidNickname);
+        }
+        Ok(())
+    }
+
+    // Trims an optional profile field, turning empty values into None.
+    fn check_field(name: &str, value: &str, max: u64) -> Result<Option<String>, StratError> {
+        let value = value.trim();
+        if value.chars().count() as u64 > max {
+            return Err(StratError::OversizedField(name.to_owned(), max));
+        }
+        if value.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(value.to_owned()))
+    }
+
     // Turns Diesel Error strings into our custom error types.
     fn match_errors(e: dsl_err) -> StratError {
         match e.to_string().as_str() {
@@ -125,21 +621,253 @@ impl User {
         }
     }
 
-    // Function for hasing passwords (duh)
-    fn hash_pass(password: &str) -> String {
-        let salt = gen_random(30);
-        let config = Config::default();
-        argon2::hash_encoded(password.as_ref(), salt.as_ref(), &config).unwrap()
+    // Creates the view of this user that anyone can see.
+    pub fn to_public(&self, posts: i64, follows: FollowCounts) -> PublicProfile {
+        let (avatar, banner) = self.get_image_urls();
+        PublicProfile {
+            id: self.id.clone(),
+            nickname: self.nickname.clone(),
+            display_name: self.display_name.clone(),
+            bio: self.bio.clone(),
+            location: self.location.clone(),
+            website: self.website.clone(),
+            avatar,
+            banner,
+            protected: self.protected,
+            rank: self.rank,
+            role: Role::of(self).name(),
+            created_at: self.created_at,
+            posts,
+            followers: follows.get_followers(),
+            following: follows.get_following(),
+        }
+    }
+
+    // Creates the view of this user that staff can see, with its account state.
+    pub fn to_admin(&self, posts: i64, follows: FollowCounts, sessions: usize) -> AdminProfile {
+        AdminProfile {
+            profile: self.to_private(posts, follows),
+            status: self.status.clone(),
+            status_until: self.status_until,
+            status_reason: self.status_reason.clone(),
+            deletion_scheduled: self.deletion_scheduled,
+            sessions,
+        }
+    }
+
+    // Creates the view of this user that only they can see.
+    pub fn to_private(&self, posts: i64, follows: FollowCounts) -> PrivateProfile {
+        let (avatar, banner) = self.get_image_urls();
+        PrivateProfile {
+            id: self.id.clone(),
+            nickname: self.nickname.clone(),
+            display_name: self.display_name.clone(),
+            bio: self.bio.clone(),
+            location: self.location.clone(),
+            website: self.website.clone(),
+            avatar,
+            banner,
+            protected: self.protected,
+            email: self.email.clone(),
+            verified: self.verified,
+            totp_enabled: self.totp_enabled,
+            rank: self.rank,
+            role: Role::of(self).name(),
+            is_priv: self.is_priv,
+            updated_at: self.updated_at,
+            created_at: self.created_at,
+            posts,
+            followers: follows.get_followers(),
+            following: follows.get_following(),
+        }
     }
 
-    // Checks if the submitted password matches our hash
-    fn verify_pass(password: &str, encoded: &str) -> bool {
-        return argon2::verify_encoded(encoded, password.as_ref()).unwrap();
+    // Kept up to date by Follow as follows are made and removed.
+    pub fn get_follow_counts(&self) -> Result<FollowCounts, StratError> {
+        FollowCounts::get(&self.id)
+    }
+
+    // Creates the smallest view of this user, for when they're listed with many others.
+    pub fn to_summary(&self) -> UserSummary {
+        UserSummary {
+            id: self.id.clone(),
+            nickname: self.nickname.clone(),
+            display_name: self.display_name.clone(),
+            avatar: self
+                .avatar
+                .as_ref()
+                .map(|key| images::urls(ImageKind::Avatar, key)),
+        }
+    }
+
+    pub fn get_nickname(&self) -> &str {
+        &self.nickname
     }
 
     pub fn get_rank(&self) -> i32 {
         self.rank
     }
+
+    pub fn is_priv(&self) -> bool {
+        self.is_priv
+    }
+
+    pub fn get_email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    pub fn is_totp_enabled(&self) -> bool {
+        self.totp_enabled
+    }
+
+    // Checks if the submitted password matches this user's
+    pub fn check_password(&self, password: &str) -> bool {
+        password::verify(password, &self.password)
+    }
+}
+
+// The state of a user's account, anything but Active stops them from logging in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccountStatus {
+    Active,
+    // Until a time, or until they're unsuspended if there isn't one.
+    Suspended(Option<NaiveDateTime>),
+    Banned,
+    Deactivated,
+}
+
+// The parts of a User shown when they're listed with many others.
+#[derive(Serialize, Debug)]
+pub struct UserSummary {
+    id: String,
+    nickname: String,
+    display_name: Option<String>,
+    avatar: Option<ImageUrls>,
+}
+
+// The parts of a User that anyone can see.
+#[derive(Serialize, Debug)]
+pub struct PublicProfile {
+    id: String,
+    nickname: String,
+    display_name: Option<String>,
+    bio: Option<String>,
+    location: Option<String>,
+    website: Option<String>,
+    avatar: Option<ImageUrls>,
+    banner: Option<ImageUrls>,
+    protected: bool,
+    rank: i32,
+    role: &'static str,
+    created_at: NaiveDateTime,
+    posts: i64,
+    followers: i64,
+    following: i64,
+}
+
+// The parts of a User that only they can see, still without any secrets.
+#[derive(Serialize, Debug)]
+pub struct PrivateProfile {
+    id: String,
+    nickname: String,
+    display_name: Option<String>,
+    bio: Option<String>,
+    location: Option<String>,
+    website: Option<String>,
+    avatar: Option<ImageUrls>,
+    banner: Option<ImageUrls>,
+    protected: bool,
+    email: String,
+    verified: bool,
+    totp_enabled: bool,
+    rank: i32,
+    role: &'static str,
+    is_priv: bool,
+    updated_at: NaiveDateTime,
+    created_at: NaiveDateTime,
+    posts: i64,
+    followers: i64,
+    following: i64,
+}
+
+// The parts of a User that staff can see, still without any secrets.
+#[derive(Serialize, Debug)]
+pub struct AdminProfile {
+    #[serde(flatten)]
+    profile: PrivateProfile,
+    status: String,
+    status_until: Option<NaiveDateTime>,
+    status_reason: Option<String>,
+    deletion_scheduled: Option<NaiveDateTime>,
+    sessions: usize,
+}
+
+// A single-use token allowing a user to set a new password without logging in.
+#[derive(Queryable, Insertable, Debug)]
+pub struct PasswordReset {
+    token: String,
+    owner: String,
+    expiry: NaiveDateTime,
+    created: NaiveDateTime,
+}
+
+impl PasswordReset {
+    // Creates a new reset for a user but doesn't save it.
+    // Only the hash of the token is kept, the raw token is returned to be mailed.
+    pub fn new(owner: String) -> (Self, String) {
+        let token = gen_random(40);
+        let reset = Self {
+            token: hash_token(&token),
+            owner,
+            expiry: chrono::Local::now().naive_local() + Duration::hours(1),
+            created: chrono::Local::now().naive_local(),
+        };
+        (reset, token)
+    }
+
+    // Saves the reset, replacing any older resets the user had.
+    pub fn save_reset(&self) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                diesel::delete(reset_dsl::password_resets.filter(reset_dsl::owner.eq(&self.owner)))
+                    .execute(db)?;
+                diesel::insert_into(password_resets::table)
+                    .values(self)
+                    .execute(db)
+            });
+            match rslt {
+                Ok(_) => return None,
+                Err(_e) => return Some(StratError::Unknown),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Uses up a reset token, returning the ID of the user it belongs to.
+    pub fn consume(token: &str) -> Result<String, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            // Deleting and returning in one statement means a token can't be used twice.
+            let reset: QueryResult<Self> = diesel::delete(
+                reset_dsl::password_resets.filter(reset_dsl::token.eq(hash_token(token))),
+            )
+            .get_result::<Self>(db);
+            match reset {
+                Ok(r) if r.expiry < chrono::Local::now().naive_local() => {
+                    Err(StratError::ResetExpired)
+                }
+                Ok(r) => Ok(r.owner),
+                Err(_e) => Err(StratError::UnknownReset),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -153,3 +881,288 @@ pub struct UserLoginable {
     pub email: String,
     pub password: String,
 }
+
+// A single-use token proving that an email address belongs to a user.
+#[derive(Queryable, Insertable, Debug)]
+pub struct EmailVerification {
+    token: String,
+    owner: String,
+    email: String,
+    expiry: NaiveDateTime,
+    created: NaiveDateTime,
+}
+
+impl EmailVerification {
+    // Creates a new verification for an address but doesn't save it.
+    // Only the hash of the token is kept, the raw token is returned to be mailed.
+    pub fn new(owner: String, email: String) -> (Self, String) {
+        let token = gen_random(40);
+        let verification = Self {
+            token: hash_token(&token),
+            owner,
+            email,
+            expiry: chrono::Local::now().naive_local() + Duration::days(1),
+            created: chrono::Local::now().naive_local(),
+        };
+        (verification, token)
+    }
+
+    // Saves the verification, replacing any older verifications the user had.
+    pub fn save_verification(&self) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = db.transaction::<_, dsl_err, _>(|| {
+                diesel::delete(
+                    verify_dsl::email_verifications.filter(verify_dsl::owner.eq(&self.owner)),
+                )
+                .execute(db)?;
+                diesel::insert_into(email_verifications::table)
+                    .values(self)
+                    .execute(db)
+            });
+            match rslt {
+                Ok(_) => return None,
+                Err(_e) => return Some(StratError::Unknown),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Uses up a verification token, returning the verification it belonged to.
+    pub fn consume(token: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let verification: QueryResult<Self> = diesel::delete(
+                verify_dsl::email_verifications.filter(verify_dsl::token.eq(hash_token(token))),
+            )
+            .get_result::<Self>(db);
+            match verification {
+                Ok(v) if v.expiry < chrono::Local::now().naive_local() => {
+                    Err(StratError::VerificationExpired)
+                }
+                Ok(v) => Ok(v),
+                Err(_e) => Err(StratError::UnknownVerification),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    pub fn get_owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn get_email(&self) -> &str {
+        &self.email
+    }
+}
+
+// How long an export can be pending before it's taken as failed.
+// Builds don't survive a restart or a panic, so without this they'd stay pending forever.
+const EXPORT_BUILD_MINUTES: i64 = 30;
+
+// An archive of everything stored about a user, built in the background.
+#[derive(Queryable, Insertable, Serialize, AsChangeset, Debug)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct DataExport {
+    id: String,
+    #[serde(skip)]
+    owner: String,
+    status: String,
+    created: NaiveDateTime,
+    completed: Option<NaiveDateTime>,
+    expiry: Option<NaiveDateTime>,
+}
+
+impl DataExport {
+    // Creates a new pending export but doesn't save it.
+    pub fn new(owner: String) -> Self {
+        let now = chrono::Local::now().naive_local();
+        Self {
+            id: gen_random(24),
+            owner,
+            status: "pending".to_owned(),
+            created: now,
+            completed: None,
+            // Replaced once it's built, this only matters if the build never finishes.
+            expiry: Some(now + Duration::hours(CONFIG.export_expiry_hours)),
+        }
+    }
+
+    // Finds an export using its ID.
+    pub fn get_by_id(id: &str) -> Result<Self, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let export: QueryResult<Self> = export_dsl::data_exports.find(id).first::<Self>(db);
+            match export {
+                Ok(e) => Ok(e),
+                Err(_e) => Err(StratError::UnknownExport),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Finds an export of a user that is still being built, if there is one.
+    // Exports that have been pending too long are ignored, their build was lost.
+    pub fn get_pending_for(owner: &str) -> Result<Option<Self>, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let export: QueryResult<Option<Self>> = export_dsl::data_exports
+                .filter(export_dsl::owner.eq(owner))
+                .filter(export_dsl::status.eq("pending"))
+                .filter(export_dsl::created.gt(Self::stale_cutoff()))
+                .first::<Self>(db)
+                .optional();
+            match export {
+                Ok(e) => Ok(e),
+                Err(_e) => Err(StratError::Unknown),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Saves the export, inserting it if it's new.
+    pub fn save_export(&self) -> Option<StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = diesel::insert_into(data_exports::table)
+                .values(self)
+                .on_conflict(export_dsl::id)
+                .do_update()
+                .set(self)
+                .execute(db);
+            match rslt {
+                Ok(_) => return None,
+                Err(_e) => return Some(StratError::Unknown),
+            }
+        }
+        Some(StratError::DbFailed)
+    }
+
+    // Marks every export that has been pending too long as failed, returning how many there were.
+    pub fn fail_stale() -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let now = chrono::Local::now().naive_local();
+            let rslt = diesel::update(
+                export_dsl::data_exports
+                    .filter(export_dsl::status.eq("pending"))
+                    .filter(export_dsl::created.le(Self::stale_cutoff())),
+            )
+            .set((
+                export_dsl::status.eq("failed"),
+                export_dsl::completed.eq(now),
+                export_dsl::expiry.eq(now + Duration::hours(CONFIG.export_expiry_hours)),
+            ))
+            .execute(db);
+            match rslt {
+                Ok(n) => Ok(n),
+                Err(_e) => Err(StratError::Unknown),
+            }
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    fn stale_cutoff() -> NaiveDateTime {
+        chrono::Local::now().naive_local() - Duration::minutes(EXPORT_BUILD_MINUTES)
+    }
+
+    // Deletes every export past its expiry along with its archive, returning how many were removed.
+    // Archives without an export (like those of deleted users) are removed too, as long as
+    // they look like ours and are older than a build, so an export started meanwhile is kept.
+    pub fn delete_expired() -> Result<usize, StratError> {
+        if can_connect() {
+            let db: &PgConnection = &get_database();
+            let rslt = diesel::delete(
+                export_dsl::data_exports
+                    .filter(export_dsl::expiry.lt(chrono::Local::now().naive_local())),
+            )
+            .execute(db);
+            let count = match rslt {
+                Ok(n) => n,
+                Err(_e) => return Err(StratError::Unknown),
+            };
+            let live: HashSet<String> = match export_dsl::data_exports
+                .select(export_dsl::id)
+                .load::<String>(db)
+            {
+                Ok(ids) => ids.into_iter().collect(),
+                Err(_e) => return Err(StratError::Unknown),
+            };
+            // The directory only exists once something has been exported.
+            let cutoff = SystemTime::now()
+                - std::time::Duration::from_secs(EXPORT_BUILD_MINUTES as u64 * 60);
+            if let Ok(entries) = fs::read_dir(&CONFIG.export_dir) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if !Self::is_archive(&path) {
+                        continue;
+                    }
+                    let recent = entry
+                        .metadata()
+                        .and_then(|m| m.modified())
+                        .map(|modified| modified > cutoff)
+                        .unwrap_or(true);
+                    let id = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+                    if !recent && !live.contains(id) {
+                        if let Err(e) = fs::remove_file(&path) {
+                            eprintln!("Failed to remove export {}: {}", path.display(), e);
+                        }
+                    }
+                }
+            }
+            Ok(count)
+        } else {
+            Err(StratError::DbFailed)
+        }
+    }
+
+    // Checks a file is named like an archive, an export ID with a .zip extension.
+    fn is_archive(path: &Path) -> bool {
+        let id = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        path.extension().map(|e| e == "zip").unwrap_or(false)
+            && id.len() == 24
+            && id.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+
+    // Marks the export as built, it can be downloaded until it expires.
+    pub fn complete(&mut self) {
+        let now = chrono::Local::now().naive_local();
+        self.status = "ready".to_owned();
+        self.completed = Some(now);
+        self.expiry = Some(now + Duration::hours(CONFIG.export_expiry_hours));
+    }
+
+    // Marks the export as failed, it's cleaned up after the same expiry as a built one.
+    pub fn fail(&mut self) {
+        let now = chrono::Local::now().naive_local();
+        self.status = "failed".to_owned();
+        self.completed = Some(now);
+        self.expiry = Some(now + Duration::hours(CONFIG.export_expiry_hours));
+    }
+
+    // Checks if the archive is built and hasn't expired yet.
+    pub fn is_ready(&self) -> bool {
+        self.status == "ready"
+            && self
+                .expiry
+                .map(|e| e > chrono::Local::now().naive_local())
+                .unwrap_or(false)
+    }
+
+    // Where the archive is stored.
+    pub fn get_path(&self) -> PathBuf {
+        PathBuf::from(&CONFIG.export_dir).join(format!("{}.zip", self.id))
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_owner(&self) -> &str {
+        &self.owner
+    }
+}
@@ -0,0 +1,109 @@
+use dotenv::dotenv;
+use lazy_static::lazy_static;
+use std::{env, str::FromStr};
+
+pub struct Config {
+    // Cookies
+    pub cookie_secure: bool,
+    pub cookie_same_site: String,
+    pub cookie_domain: Option<String>,
+    // Mail
+    pub mailer: String,
+    pub mail_from: String,
+    pub smtp_host: Option<String>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    // Storage
+    pub storage: String,
+    pub storage_dir: String,
+    pub storage_url: String,
+    // Accounts
+    pub require_verified_to_post: bool,
+    pub nickname_change_days: i64,
+    pub deletion_grace_days: i64,
+    pub export_dir: String,
+    pub export_expiry_hours: i64,
+    // Login throttling
+    pub login_max_failures: i32,
+    pub login_ip_max_failures: i32,
+    pub login_lockout_base: i64,
+    pub login_lockout_max: i64,
+    // Sessions
+    pub session_gc_interval: u64,
+    // Passwords
+    pub argon2_variant: String,
+    pub argon2_mem_cost: u32,
+    pub argon2_time_cost: u32,
+    pub argon2_lanes: u32,
+    pub password_min_length: usize,
+    pub password_blocklist: Option<String>,
+}
+
+lazy_static! {
+    pub static ref CONFIG: Config = {
+        dotenv().ok();
+        let cookie_same_site = env::var("COOKIE_SAMESITE").unwrap_or_else(|_| "Strict".to_owned());
+        if !["Strict", "Lax", "None"].contains(&cookie_same_site.as_str()) {
+            panic!("COOKIE_SAMESITE must be one of Strict, Lax or None");
+        }
+        let argon2_variant = env::var("ARGON2_VARIANT").unwrap_or_else(|_| "argon2id".to_owned());
+        if !["argon2i", "argon2d", "argon2id"].contains(&argon2_variant.as_str()) {
+            panic!("ARGON2_VARIANT must be one of argon2i, argon2d or argon2id");
+        }
+        let argon2_lanes = env_num("ARGON2_LANES", 1);
+        let argon2_mem_cost = env_num("ARGON2_MEM_COST", 19456);
+        if argon2_lanes < 1 || argon2_mem_cost < 8 * argon2_lanes {
+            panic!("ARGON2_LANES must be at least 1 and ARGON2_MEM_COST at least 8 times it");
+        }
+        Config {
+            cookie_secure: env_bool("COOKIE_SECURE", true),
+            cookie_same_site,
+            cookie_domain: env::var("COOKIE_DOMAIN").ok(),
+            mailer: env::var("MAILER").unwrap_or_else(|_| "log".to_owned()),
+            mail_from: env::var("MAIL_FROM")
+                .unwrap_or_else(|_| "Stratosphere <noreply@localhost>".to_owned()),
+            smtp_host: env::var("SMTP_HOST").ok(),
+            smtp_username: env::var("SMTP_USERNAME").ok(),
+            smtp_password: env::var("SMTP_PASSWORD").ok(),
+            storage: env::var("STORAGE").unwrap_or_else(|_| "local".to_owned()),
+            storage_dir: env::var("STORAGE_DIR").unwrap_or_else(|_| "uploads".to_owned()),
+            storage_url: env::var("STORAGE_URL").unwrap_or_else(|_| "/media".to_owned()),
+            require_verified_to_post: env_bool("REQUIRE_VERIFIED_TO_POST", false),
+            nickname_change_days: env_num("NICKNAME_CHANGE_DAYS", 30),
+            deletion_grace_days: env_num("DELETION_GRACE_DAYS", 0),
+            export_dir: env::var("EXPORT_DIR").unwrap_or_else(|_| "exports".to_owned()),
+            export_expiry_hours: env_num("EXPORT_EXPIRY_HOURS", 48),
+            login_max_failures: env_num("LOGIN_MAX_FAILURES", 5),
+            login_ip_max_failures: env_num("LOGIN_IP_MAX_FAILURES", 20),
+            login_lockout_base: env_num("LOGIN_LOCKOUT_BASE_SECS", 30),
+            login_lockout_max: env_num("LOGIN_LOCKOUT_MAX_SECS", 3600),
+            session_gc_interval: env_num("SESSION_GC_INTERVAL_SECS", 3600),
+            argon2_variant,
+            argon2_mem_cost,
+            argon2_time_cost: env_num("ARGON2_TIME_COST", 2).max(1),
+            argon2_lanes,
+            password_min_length: env_num("PASSWORD_MIN_LENGTH", 8),
+            password_blocklist: env::var("PASSWORD_BLOCKLIST").ok(),
+        }
+    };
+}
+
+// Reads a boolean from the environment, falling back to the default if it's unset.
+fn env_bool(key: &str, default: bool) -> bool {
+    match env::var(key) {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be true or false", key)),
+        Err(_e) => default,
+    }
+}
+
+// Reads a number from the environment, falling back to the default if it's unset.
+fn env_num<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number", key)),
+        Err(_e) => default,
+    }
+}
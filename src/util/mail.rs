@@ -0,0 +1,82 @@
+use crate::{error::StratError, util::config::CONFIG};
+use lazy_static::lazy_static;
+use lettre::{
+    smtp::authentication::Credentials, SmtpClient, SmtpTransport, Transport as LettreTransport,
+};
+use lettre_email::EmailBuilder;
+use std::sync::Mutex;
+
+// Anything capable of delivering an email.
+pub trait Mailer: Send + Sync {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), StratError>;
+}
+
+lazy_static! {
+    pub static ref MAILER: Box<dyn Mailer> = match CONFIG.mailer.as_str() {
+        "smtp" => Box::new(SmtpMailer::from_config()),
+        "log" => Box::new(LogMailer),
+        _ => panic!("MAILER must be either smtp or log"),
+    };
+}
+
+// Gives us the configured mailer
+pub fn get_mailer() -> &'static dyn Mailer {
+    MAILER.as_ref()
+}
+
+// Sends mail through an SMTP server.
+pub struct SmtpMailer {
+    transport: Mutex<SmtpTransport>,
+    from: String,
+}
+
+impl SmtpMailer {
+    // Creates the mailer using the SMTP settings from the environment.
+    pub fn from_config() -> Self {
+        let host = CONFIG
+            .smtp_host
+            .as_ref()
+            .expect("SMTP_HOST must be set when using the smtp mailer");
+        let mut client = SmtpClient::new_simple(host).expect("failed to create smtp client");
+        if let (Some(user), Some(pass)) = (&CONFIG.smtp_username, &CONFIG.smtp_password) {
+            client = client.credentials(Credentials::new(user.to_owned(), pass.to_owned()));
+        }
+        Self {
+            transport: Mutex::new(client.transport()),
+            from: CONFIG.mail_from.clone(),
+        }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), StratError> {
+        let email = match EmailBuilder::new()
+            .to(to)
+            .from(self.from.as_str())
+            .subject(subject)
+            .text(body)
+            .build()
+        {
+            Ok(e) => e,
+            Err(_e) => return Err(StratError::MailFailed),
+        };
+        let mut transport = self.transport.lock().unwrap();
+        match transport.send(email.into()) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                eprintln!("Failed to send mail to {}: {}", to, e);
+                Err(StratError::MailFailed)
+            }
+        }
+    }
+}
+
+// Prints mail to the server logs instead of sending it, for development and testing.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), StratError> {
+        println!("Mail to: {}\nSubject: {}\n\n{}\n", to, subject, body);
+        Ok(())
+    }
+}
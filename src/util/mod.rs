@@ -1,3 +1,4 @@
+use self::config::CONFIG;
 use cookie::{Cookie, CookieJar};
 use hyper::{
     header::{self, HeaderValue},
@@ -6,9 +7,13 @@ use hyper::{
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use serde::de::DeserializeOwned;
 use serde_json::Value;
-use std::iter;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, iter};
 
+pub mod config;
 pub mod db;
+pub mod mail;
+pub mod storage;
 
 // Takes a JSON Value and creats a Response.
 pub fn json_response(json: Value) -> Response<Body> {
@@ -27,6 +32,32 @@ pub async fn parse_body<T: DeserializeOwned>(req: &mut Request<Body>) -> Result<
     serde_json::from_slice(&body).map_err(|e| format!("Failed to parse JSON: {}", e))
 }
 
+// Takes a Request with a form urlencoded body and parses it into its fields.
+pub async fn parse_form(req: &mut Request<Body>) -> Result<HashMap<String, String>, String> {
+    let body = hyper::body::to_bytes(req.body_mut())
+        .await
+        .map_err(|_| "Internal Server Error".to_string())?;
+    Ok(form_urlencoded::parse(&body).into_owned().collect())
+}
+
+// Gets a single decoded parameter from the query string of a request.
+pub fn get_query(req: &Request<Body>, name: &str) -> Option<String> {
+    let query = req.uri().query()?;
+    form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+// Gets the "page" query parameter of a listing, 0 if it's missing or invalid.
+// It's capped so the offset it's turned into can't overflow.
+pub fn get_page(req: &Request<Body>) -> i64 {
+    const MAX_PAGE: i64 = 100_000;
+    get_query(req, "page")
+        .and_then(|p| p.parse::<i64>().ok())
+        .unwrap_or(0)
+        .clamp(0, MAX_PAGE)
+}
+
 // Generates a random string of length
 pub fn gen_random(length: usize) -> String {
     let mut rng = thread_rng();
@@ -38,6 +69,50 @@ pub fn gen_random(length: usize) -> String {
     result
 }
 
+// Hashes a token so it can be stored and looked up without keeping the raw value.
+// Tokens are long and random, so a fast unsalted hash is enough here.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Creates a Set-Cookie header value using the configured cookie attributes.
+// A max_age of 0 tells the client to delete the cookie.
+pub fn cookie_header(
+    name: &str,
+    value: &str,
+    path: &str,
+    http_only: bool,
+    max_age: i64,
+) -> HeaderValue {
+    let mut cookie = format!(
+        "{}={};Path={};Max-Age={};SameSite={}",
+        name,
+        value,
+        path,
+        max_age.max(0),
+        CONFIG.cookie_same_site
+    );
+    if http_only {
+        cookie.push_str(";HttpOnly");
+    }
+    if CONFIG.cookie_secure {
+        cookie.push_str(";Secure");
+    }
+    if let Some(domain) = &CONFIG.cookie_domain {
+        cookie.push_str(&format!(";Domain={}", domain));
+    }
+    HeaderValue::from_str(&cookie).expect("Cookies should be valid")
+}
+
+// Compares two strings in constant time, so secrets can't be guessed by timing.
+pub fn constant_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
 pub fn parse_cookies(headers: &HeaderMap<HeaderValue>) -> CookieJar {
     let mut jar = CookieJar::new();
     if let Some(cookies) = headers.get("Cookie") {
@@ -53,3 +128,32 @@ pub fn parse_cookies(headers: &HeaderMap<HeaderValue>) -> CookieJar {
     }
     jar
 }
+
+// Gets the token from an "Authorization: Bearer <token>" header, if there is one.
+pub fn parse_bearer(headers: &HeaderMap<HeaderValue>) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = value.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => {
+            Some(token.trim().to_owned())
+        }
+        _ => None,
+    }
+}
+
+// Gets the username and password from an "Authorization: Basic" header, if there is one.
+pub fn parse_basic(headers: &HeaderMap<HeaderValue>) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = value.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(encoded)) if scheme.eq_ignore_ascii_case("basic") => {
+            let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+            let mut credentials = decoded.splitn(2, ':');
+            Some((
+                credentials.next()?.to_owned(),
+                credentials.next()?.to_owned(),
+            ))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{error::StratError, util::config::CONFIG};
+use hyper::{header::CONTENT_TYPE, Body, Request, Response, StatusCode};
+use lazy_static::lazy_static;
+use routerify::ext::RequestExt;
+use std::{fs, path::PathBuf};
+
+// Anything capable of storing uploaded files and serving them from a URL.
+pub trait Storage: Send + Sync {
+    fn put(&self, name: &str, data: &[u8], content_type: &str) -> Result<(), StratError>;
+    // Gets a stored file, or None if there isn't one with that name.
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, StratError>;
+    fn delete(&self, name: &str) -> Result<(), StratError>;
+    fn url(&self, name: &str) -> String;
+}
+
+lazy_static! {
+    pub static ref STORAGE: Box<dyn Storage> = match CONFIG.storage.as_str() {
+        "local" => Box::new(LocalStorage::from_config()),
+        _ => panic!("STORAGE must be local"),
+    };
+}
+
+// Gives us the configured storage
+pub fn get_storage() -> &'static dyn Storage {
+    STORAGE.as_ref()
+}
+
+// Stores files in a directory on this server, they're served by serve_local.
+pub struct LocalStorage {
+    dir: PathBuf,
+    base_url: String,
+}
+
+impl LocalStorage {
+    // Creates the storage using the settings from the environment.
+    pub fn from_config() -> Self {
+        Self {
+            dir: PathBuf::from(&CONFIG.storage_dir),
+            base_url: CONFIG.storage_url.trim_end_matches('/').to_owned(),
+        }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, name: &str, data: &[u8], _content_type: &str) -> Result<(), StratError> {
+        if !is_safe_name(name) {
+            return Err(StratError::Unknown);
+        }
+        if let Err(e) =
+            fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.dir.join(name), data))
+        {
+            eprintln!("Failed to store {}: {}", name, e);
+            return Err(StratError::StorageFailed);
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, StratError> {
+        if !is_safe_name(name) {
+            return Err(StratError::Unknown);
+        }
+        match fs::read(self.dir.join(name)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", name, e);
+                Err(StratError::StorageFailed)
+            }
+        }
+    }
+
+    fn delete(&self, name: &str) -> Result<(), StratError> {
+        if !is_safe_name(name) {
+            return Err(StratError::Unknown);
+        }
+        match fs::remove_file(self.dir.join(name)) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                eprintln!("Failed to delete {}: {}", name, e);
+                Err(StratError::StorageFailed)
+            }
+        }
+    }
+
+    fn url(&self, name: &str) -> String {
+        format!("{}/{}", self.base_url, name)
+    }
+}
+
+// Serves a file from local storage, guessing its type from the extension.
+pub async fn serve_local(req: Request<Body>) -> Result<Response<Body>, StratError> {
+    let name = req.param("name").unwrap();
+    let not_found = || {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap()
+    };
+    if CONFIG.storage != "local" || !is_safe_name(name) {
+        return Ok(not_found());
+    }
+    let content_type = match name.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("jpg") => "image/jpeg",
+        _ => "application/octet-stream",
+    };
+    match tokio::fs::read(PathBuf::from(&CONFIG.storage_dir).join(name)).await {
+        Ok(data) => Ok(Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(data))
+            .unwrap()),
+        Err(_e) => Ok(not_found()),
+    }
+}
+
+// Names are generated by us, but they're also taken from URLs,
+// so anything that could escape the storage directory is refused.
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}